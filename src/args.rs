@@ -0,0 +1,50 @@
+use anyhow::anyhow;
+
+/// The value of our `name` argument, given as either `--name <value>` or `--name=<value>`.
+/// `None` if we weren't given it at all.
+pub fn find_arg<I: Iterator<Item = String>>(
+    mut args: I,
+    name: &str,
+) -> Option<anyhow::Result<String>> {
+    let prefix = format!("{}=", name);
+    while let Some(arg) = args.next() {
+        let value = if arg == name {
+            args.next()
+        } else if let Some(value) = arg.strip_prefix(&prefix) {
+            Some(value.to_string())
+        } else {
+            continue;
+        };
+
+        return Some(value.ok_or_else(|| anyhow!("{} needs a value", name)));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(args: &[&str], name: &str) -> Option<anyhow::Result<String>> {
+        find_arg(args.iter().map(|arg| arg.to_string()), name)
+    }
+
+    #[test]
+    fn values_follow_or_are_joined_to_their_name() {
+        assert_eq!(
+            find(&["game", "--seed", "42"], "--seed").unwrap().unwrap(),
+            "42"
+        );
+        assert_eq!(find(&["game", "--seed=7"], "--seed").unwrap().unwrap(), "7");
+        assert_eq!(
+            find(&["game", "--level", "caves", "--seed", "1"], "--seed")
+                .unwrap()
+                .unwrap(),
+            "1"
+        );
+        assert!(find(&["game"], "--seed").is_none());
+        assert!(find(&["game", "--seeds", "3"], "--seed").is_none());
+        assert!(find(&["game", "--seed"], "--seed").unwrap().is_err());
+    }
+}
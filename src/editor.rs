@@ -0,0 +1,393 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+
+use crate::level::{save_level, Chunk, CurrentLevel, Level, LevelGrid, LevelSource, Tile};
+use crate::seed::WorldSeed;
+use crate::view_system::run_editor;
+
+/// The tiles we can paint with, picked with the number keys
+const PALETTE: [(KeyCode, Tile); 7] = [
+    (KeyCode::Key1, Tile::Floor),
+    (KeyCode::Key2, Tile::Wall),
+    (KeyCode::Key3, Tile::Water),
+    (KeyCode::Key4, Tile::Lava),
+    (KeyCode::Key5, Tile::Door),
+    (KeyCode::Key6, Tile::Spawn),
+    (KeyCode::Key7, Tile::Void),
+];
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Editor>()
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(run_editor.system())
+                    .with_system(editor_palette_system.system())
+                    .with_system(editor_paint_system.system())
+                    .with_system(editor_history_system.system())
+                    .with_system(editor_save_system.system()),
+            )
+            // our level can be reloaded whether we're editing or not
+            .add_system(editor_reload_system.system());
+    }
+}
+
+/// Edits the level we loaded from our assets by changing its `Level` asset, so our level spawner
+/// rebuilds whatever we touched just like it does when the file changes on disk.
+pub struct Editor {
+    /// what a left click paints
+    pub selected: Tile,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// every change we make to our level sends a `Modified` event just like our file changing on
+    /// disk does, so we count ours to tell them apart
+    our_modifications: usize,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Editor {
+            selected: Tile::Wall,
+            undo: vec![],
+            redo: vec![],
+            our_modifications: 0,
+        }
+    }
+}
+
+impl Editor {
+    /// Toggle `cell` of our `level` between our selected tile and a floor (or a wall if we're
+    /// painting floors)
+    fn paint(&mut self, level: &mut Level, cell: (usize, usize)) {
+        let before = match Painted::read(&level.chunk, cell) {
+            Some(before) => before,
+            None => return,
+        };
+
+        let tile = if before.tile != self.selected {
+            self.selected
+        } else if self.selected != Tile::Floor {
+            Tile::Floor
+        } else {
+            Tile::Wall
+        };
+        // our new tile brings its own floor mesh
+        let after = Painted { tile, mesh: None };
+
+        after.write(&mut level.chunk, cell);
+        self.undo.push(Edit {
+            cell,
+            before,
+            after,
+        });
+        self.redo.clear();
+    }
+
+    /// Play our last edit backwards
+    fn undo(&mut self, level: &mut Level) {
+        if let Some(edit) = self.undo.pop() {
+            edit.before.write(&mut level.chunk, edit.cell);
+            self.redo.push(edit);
+        }
+    }
+
+    /// Play the last edit we undid forwards again
+    fn redo(&mut self, level: &mut Level) {
+        if let Some(edit) = self.redo.pop() {
+            edit.after.write(&mut level.chunk, edit.cell);
+            self.undo.push(edit);
+        }
+    }
+}
+
+/// A single painted cell
+#[derive(Clone, Debug)]
+struct Edit {
+    cell: (usize, usize),
+    before: Painted,
+    after: Painted,
+}
+
+/// Everything painting a cell changes, setting a tile forgets any floor mesh a Tiled map gave it
+/// so we need to put that back too
+#[derive(Clone, Debug, PartialEq)]
+struct Painted {
+    tile: Tile,
+    mesh: Option<String>,
+}
+
+impl Painted {
+    fn read(chunk: &Chunk, (x, z): (usize, usize)) -> Option<Painted> {
+        Some(Painted {
+            tile: chunk.get(x, z)?,
+            mesh: chunk.floor_mesh_override(x, z).map(|mesh| mesh.to_string()),
+        })
+    }
+
+    fn write(&self, chunk: &mut Chunk, (x, z): (usize, usize)) {
+        chunk.set(x, z, self.tile);
+        chunk.set_floor_mesh(x, z, self.mesh.clone());
+    }
+}
+
+/// The level we're editing, we can only edit levels from our assets. Borrowing our level counts
+/// as a modification whether we change it or not.
+fn editing<'a>(
+    current_level: &Option<Res<CurrentLevel>>,
+    levels: &'a mut Assets<Level>,
+    our_modifications: &mut usize,
+) -> Option<&'a mut Level> {
+    let level = current_level
+        .as_ref()
+        .and_then(move |current| levels.get_mut(&current.handle));
+    if level.is_some() {
+        *our_modifications += 1;
+    }
+
+    level
+}
+
+fn editor_palette_system(keyboard_input: Res<Input<KeyCode>>, mut editor: ResMut<Editor>) {
+    for (key, tile) in PALETTE.iter() {
+        if keyboard_input.just_pressed(*key) {
+            editor.selected = *tile;
+            log::info!("Painting {:?}", tile);
+        }
+    }
+}
+
+/// Left click paints the tile under our cursor
+fn editor_paint_system(
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    grid: Res<LevelGrid>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut levels: ResMut<Assets<Level>>,
+    mut editor: ResMut<Editor>,
+    camera_query: Query<(&GlobalTransform, &Camera)>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let tile = camera_query.iter().find_map(|(transform, camera)| {
+        if camera.name.as_deref() == Some(CAMERA_3D) {
+            grid.tile_under_cursor(window, camera, transform)
+        } else {
+            None
+        }
+    });
+    let (tile_x, tile_z) = match tile {
+        Some(tile) => tile,
+        None => return,
+    };
+
+    let level = match editing(&current_level, &mut levels, &mut editor.our_modifications) {
+        Some(level) => level,
+        None => return,
+    };
+    let (offset_x, offset_z) = level.offset();
+    let (x, z) = (tile_x - offset_x, tile_z - offset_z);
+    if x < 0 || z < 0 {
+        return;
+    }
+    editor.paint(level, (x as usize, z as usize));
+}
+
+/// Ctrl+Z undoes our last edit, Ctrl+Y (or Ctrl+Shift+Z) redoes it
+fn editor_history_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut levels: ResMut<Assets<Level>>,
+    mut editor: ResMut<Editor>,
+) {
+    let control =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    if !control {
+        return;
+    }
+
+    let redo = keyboard_input.just_pressed(KeyCode::Y)
+        || (shift && keyboard_input.just_pressed(KeyCode::Z));
+    let undo = !shift && keyboard_input.just_pressed(KeyCode::Z);
+
+    if !undo && !redo {
+        return;
+    }
+
+    // don't lose our history if our level isn't loaded
+    let editor = &mut *editor;
+    let level = match editing(&current_level, &mut levels, &mut editor.our_modifications) {
+        Some(level) => level,
+        None => return,
+    };
+    if undo {
+        editor.undo(level);
+    } else {
+        editor.redo(level);
+    }
+}
+
+/// Our history only makes sense for the level we made it on, so we forget it when a new level is
+/// loaded or our file is changed out from under us
+fn editor_reload_system(
+    mut level_events: EventReader<AssetEvent<Level>>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut editor: ResMut<Editor>,
+) {
+    let current = match current_level {
+        Some(current) => current,
+        None => return,
+    };
+
+    for event in level_events.iter() {
+        match event {
+            AssetEvent::Created { handle } if *handle == current.handle => {
+                editor.undo.clear();
+                editor.redo.clear();
+                editor.our_modifications = 0;
+            }
+            AssetEvent::Modified { handle } if *handle == current.handle => {
+                if editor.our_modifications > 0 {
+                    editor.our_modifications -= 1;
+                } else {
+                    log::info!("Our level changed on disk, clearing our edit history");
+                    editor.undo.clear();
+                    editor.redo.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ctrl+S saves our level next to the file it came from as `.ron`, i.e.
+/// `assets/levels/arena.level` becomes `assets/levels/arena.ron`. Our generated levels are saved
+/// along with the seed they grew from, i.e. `assets/levels/caves_42.ron`.
+fn editor_save_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    level_source: Res<LevelSource>,
+    seed: Res<WorldSeed>,
+    current_level: Option<Res<CurrentLevel>>,
+    levels: Res<Assets<Level>>,
+) {
+    let control =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !(control && keyboard_input.just_pressed(KeyCode::S)) {
+        return;
+    }
+
+    let path = match &*level_source {
+        LevelSource::Asset(path) => Path::new("assets").join(path).with_extension("ron"),
+        LevelSource::Caves => Path::new("assets/levels").join(format!("caves_{}.ron", seed.0)),
+        LevelSource::Dungeon => Path::new("assets/levels").join(format!("dungeon_{}.ron", seed.0)),
+        LevelSource::Streamed => {
+            log::warn!("We can only save a single level, not our streamed world");
+            return;
+        }
+    };
+    let level = match current_level
+        .as_ref()
+        .and_then(|current| levels.get(&current.handle))
+    {
+        Some(level) => level,
+        None => return,
+    };
+
+    match save_level(level, &path) {
+        Ok(()) => log::info!("Saved {}", path.display()),
+        Err(e) => log::error!("{:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level() -> Level {
+        let mut chunk = Chunk::new(3, 3, Tile::Floor);
+        chunk.set_floor_mesh(1, 1, Some("floor_cracked".to_string()));
+        Level::new(chunk)
+    }
+
+    fn cell(level: &Level, (x, z): (usize, usize)) -> (Option<Tile>, Option<&str>) {
+        (level.chunk.get(x, z), level.chunk.floor_mesh_override(x, z))
+    }
+
+    #[test]
+    fn painting_toggles_our_selected_tile() {
+        let mut level = level();
+        let mut editor = Editor::default();
+
+        editor.paint(&mut level, (0, 0));
+        assert_eq!(level.chunk.get(0, 0), Some(Tile::Wall));
+        editor.paint(&mut level, (0, 0));
+        assert_eq!(level.chunk.get(0, 0), Some(Tile::Floor));
+
+        editor.selected = Tile::Floor;
+        editor.paint(&mut level, (0, 0));
+        assert_eq!(level.chunk.get(0, 0), Some(Tile::Wall));
+
+        // and painting off our level does nothing at all
+        editor.paint(&mut level, (3, 0));
+        assert_eq!(editor.undo.len(), 3);
+    }
+
+    #[test]
+    fn undo_and_redo_bring_back_our_custom_meshes() {
+        let mut level = level();
+        let mut editor = Editor {
+            selected: Tile::Lava,
+            ..Default::default()
+        };
+
+        editor.paint(&mut level, (1, 1));
+        assert_eq!(cell(&level, (1, 1)), (Some(Tile::Lava), None));
+
+        editor.undo(&mut level);
+        assert_eq!(
+            cell(&level, (1, 1)),
+            (Some(Tile::Floor), Some("floor_cracked"))
+        );
+        assert_eq!(level, self::level());
+
+        editor.redo(&mut level);
+        assert_eq!(cell(&level, (1, 1)), (Some(Tile::Lava), None));
+
+        editor.undo(&mut level);
+        assert_eq!(level, self::level());
+    }
+
+    #[test]
+    fn our_history_plays_back_in_order() {
+        let mut level = level();
+        let mut editor = Editor::default();
+        editor.paint(&mut level, (1, 1));
+        editor.paint(&mut level, (1, 1));
+        editor.paint(&mut level, (2, 2));
+
+        editor.undo(&mut level);
+        editor.undo(&mut level);
+        assert_eq!(cell(&level, (1, 1)), (Some(Tile::Wall), None));
+        assert_eq!(cell(&level, (2, 2)), (Some(Tile::Floor), None));
+        editor.undo(&mut level);
+        editor.undo(&mut level);
+        assert_eq!(level, self::level());
+
+        editor.redo(&mut level);
+        assert_eq!(cell(&level, (1, 1)), (Some(Tile::Wall), None));
+
+        // painting something new forgets what we undid
+        editor.paint(&mut level, (0, 2));
+        editor.redo(&mut level);
+        assert_eq!(cell(&level, (1, 1)), (Some(Tile::Wall), None));
+        assert_eq!(cell(&level, (2, 2)), (Some(Tile::Floor), None));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+
+use crate::level::{Chunk, Direction, Level, Tile};
+
+/// The characters every `.level` file understands, a header can add to or override these
+pub(super) const DEFAULT_LEGEND: [(char, Tile); 7] = [
+    ('#', Tile::Wall),
+    ('.', Tile::Floor),
+    ('@', Tile::Spawn),
+    ('~', Tile::Water),
+    ('^', Tile::Lava),
+    ('+', Tile::Door),
+    (' ', Tile::Void),
+];
+
+/// The characters of our ramp map, pointing the way each ramp climbs
+pub(super) const RAMPS: [(char, Direction); 4] = [
+    ('^', Direction::North),
+    ('>', Direction::East),
+    ('v', Direction::South),
+    ('<', Direction::West),
+];
+
+/// Separates an optional header from our map
+const HEADER_END: &str = "---";
+/// Starts an optional elevation map after our tiles
+const ELEVATIONS_START: &str = "--- elevations";
+/// Starts an optional ramp map after our tiles
+const RAMPS_START: &str = "--- ramps";
+/// Each digit of our elevation map raises a floor by this many tiles, and each letter sinks it
+const ELEVATION_STEP: f32 = 0.25;
+/// A header line naming the gltf our markers come from
+const MARKERS_KEY: &str = "markers:";
+
+/// Loads plain text `.level` files, one character per tile. Our map can optionally be followed by
+/// an elevation map with a digit per tile raising its floor by a quarter of a tile each, or a
+/// letter sinking it (`a` by a quarter, `b` by a half and so on). A ramp map can then slope floors
+/// up to the cell that `^`, `>`, `v` or `<` points at, anything else stays flat:
+///
+/// ```text
+/// // anything above the `---` is our header, `<char> = <tile>` overrides our legend
+/// x = lava
+/// // and `markers: <gltf>` spawns the markers from that gltf into our level
+/// markers: models.gltf
+/// ---
+/// #######
+/// #.@.x.#
+/// #######
+/// --- elevations
+/// 0000000
+/// 0001240
+/// 0000000
+/// --- ramps
+/// .......
+/// ..>....
+/// .......
+/// ```
+#[derive(Default)]
+pub struct AsciiLevelLoader;
+
+impl AssetLoader for AsciiLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let text = std::str::from_utf8(bytes)?;
+            let level = parse_level(text)
+                .with_context(|| format!("Couldn't parse {}", load_context.path().display()))?;
+
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level"]
+    }
+}
+
+/// Parse a `.level` file, our errors report 1 based lines and columns
+pub fn parse_level(text: &str) -> anyhow::Result<Level> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut legend = DEFAULT_LEGEND.iter().cloned().collect::<HashMap<_, _>>();
+    let mut markers = None;
+
+    // everything before our header end (if we have one) describes our legend
+    let map_start = match lines.iter().position(|line| line.trim_end() == HEADER_END) {
+        Some(header_end) => {
+            for (index, line) in lines[..header_end].iter().enumerate() {
+                match line.trim().strip_prefix(MARKERS_KEY) {
+                    Some(path) => markers = Some(path.trim().to_string()),
+                    None => parse_header_line(line, index + 1, &mut legend)?,
+                }
+            }
+            header_end + 1
+        }
+        None => 0,
+    };
+
+    // our map runs until the first of our optional sections
+    let map_end = lines[map_start..]
+        .iter()
+        .position(|line| line.trim_end().starts_with(HEADER_END))
+        .map_or(lines.len(), |index| map_start + index);
+
+    let rows = &lines[map_start..map_end];
+    let width = rows
+        .iter()
+        .map(|row| row.trim_end_matches('\r').chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut chunk = Chunk::new(width, rows.len(), Tile::Void);
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.trim_end_matches('\r').chars().enumerate() {
+            let tile = legend.get(&c).ok_or_else(|| {
+                anyhow!(
+                    "line {}, column {}: unknown tile '{}'",
+                    map_start + y + 1,
+                    x + 1,
+                    c
+                )
+            })?;
+            chunk.set(x, y, *tile);
+        }
+    }
+
+    let mut section_start = map_end;
+    while section_start < lines.len() {
+        let section_end = lines[section_start + 1..]
+            .iter()
+            .position(|line| line.trim_end().starts_with(HEADER_END))
+            .map_or(lines.len(), |index| section_start + 1 + index);
+        let section = &lines[section_start + 1..section_end];
+
+        match lines[section_start].trim_end() {
+            ELEVATIONS_START => parse_section(section, section_start + 1, |x, y, c| {
+                let step = match c {
+                    '0'..='9' => c.to_digit(10).unwrap() as f32,
+                    'a'..='z' => -((c as u8 - b'a') as f32 + 1.),
+                    _ => bail!("unknown elevation '{}'", c),
+                };
+                check_inside(chunk.set_elevation(x, y, step * ELEVATION_STEP))
+            })?,
+            RAMPS_START => parse_section(section, section_start + 1, |x, y, c| {
+                let direction = RAMPS
+                    .iter()
+                    .find(|(ramp, _)| *ramp == c)
+                    .map(|(_, direction)| *direction);
+                check_inside(chunk.get(x, y))?;
+                chunk.set_ramp(x, y, direction);
+                Ok(())
+            })?,
+            other => bail!("line {}: unknown section '{}'", section_start + 1, other),
+        }
+
+        section_start = section_end;
+    }
+
+    let mut level = Level::new(chunk);
+    level.markers = markers;
+
+    Ok(level)
+}
+
+/// Run `parse` over every character of a section starting after line `start`, with the line and
+/// column of the character in any error it returns
+fn parse_section<F: FnMut(usize, usize, char) -> anyhow::Result<()>>(
+    rows: &[&str],
+    start: usize,
+    mut parse: F,
+) -> anyhow::Result<()> {
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.trim_end_matches('\r').chars().enumerate() {
+            parse(x, y, c).with_context(|| format!("line {}, column {}", start + y + 1, x + 1))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn check_inside<T>(cell: Option<T>) -> anyhow::Result<()> {
+    match cell {
+        Some(_) => Ok(()),
+        None => bail!("outside of our map"),
+    }
+}
+
+fn parse_header_line(
+    line: &str,
+    line_number: usize,
+    legend: &mut HashMap<char, Tile>,
+) -> anyhow::Result<()> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with("//") {
+        return Ok(());
+    }
+
+    // our key is the first character of the line so we can still map whitespace
+    let mut chars = line.trim_end().chars();
+    let key = chars.next().unwrap();
+    let rest = chars.as_str();
+    let tile_name = match rest.trim_start().strip_prefix('=') {
+        Some(tile_name) => tile_name.trim(),
+        None => bail!(
+            "line {}, column {}: expected `<char> = <tile>`",
+            line_number,
+            2
+        ),
+    };
+
+    // tile_name points into our line so its offset is where it starts
+    let offset = tile_name.as_ptr() as usize - line.as_ptr() as usize;
+    let column = line[..offset].chars().count() + 1;
+    let tile = tile_name
+        .parse()
+        .with_context(|| format!("line {}, column {}", line_number, column))?;
+    legend.insert(key, tile);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_set_our_legend_and_markers() {
+        let level =
+            parse_level("// a comment\nx = lava\nmarkers: models.gltf\n---\n####\n#@x#\n####\n")
+                .unwrap();
+
+        assert_eq!(level.markers.as_deref(), Some("models.gltf"));
+        assert_eq!(level.spawn(), Some((1, 1)));
+        assert_eq!(level.chunk.get(2, 1), Some(Tile::Lava));
+    }
+
+    #[test]
+    fn elevations_raise_our_floors() {
+        let level = parse_level("###\n#.#\n###\n--- elevations\n000\n040\n000\n").unwrap();
+
+        assert_eq!(level.markers, None);
+        assert_eq!(level.chunk.elevation(1, 1), Some(4. * ELEVATION_STEP));
+        assert_eq!(level.chunk.elevation(0, 0), Some(0.));
+    }
+
+    #[test]
+    fn letters_sink_our_floors_and_ramps_climb() {
+        let level = parse_level("....\n--- elevations\n0abd\n--- ramps\n>.<.\n").unwrap();
+
+        let elevations = (0..4)
+            .map(|x| level.chunk.elevation(x, 0).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(elevations, vec![0., -0.25, -0.5, -1.]);
+        assert_eq!(level.chunk.ramp(0, 0), Some(Direction::East));
+        assert_eq!(level.chunk.ramp(1, 0), None);
+        assert_eq!(level.chunk.ramp(2, 0), Some(Direction::West));
+    }
+
+    #[test]
+    fn sections_outside_our_map_are_rejected() {
+        let error = parse_level("..\n--- ramps\n..>\n").unwrap_err();
+        assert!(error.to_string().contains("line 3, column 3"), "{}", error);
+
+        let error = parse_level("..\n--- slopes\n..\n").unwrap_err();
+        assert!(error.to_string().contains("unknown section"), "{}", error);
+    }
+
+    #[test]
+    fn our_doc_example_parses() {
+        let example = include_str!("ascii.rs")
+            .split("/// ```text\n")
+            .nth(1)
+            .and_then(|rest| rest.split("/// ```").next())
+            .unwrap()
+            .lines()
+            .map(|line| line.trim_start_matches("///").trim_start_matches(' '))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let level = parse_level(&example).unwrap();
+        assert_eq!(level.chunk.get(4, 1), Some(Tile::Lava));
+        assert_eq!(level.chunk.elevation(5, 1), Some(4. * ELEVATION_STEP));
+        assert_eq!(level.chunk.ramp(2, 1), Some(Direction::East));
+    }
+
+    #[test]
+    fn unknown_tiles_report_their_line_and_column() {
+        let error = parse_level("###\n#?#\n###\n").unwrap_err();
+
+        assert!(error.to_string().contains("line 2, column 2"), "{}", error);
+    }
+}
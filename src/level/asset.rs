@@ -0,0 +1,367 @@
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::args::find_arg;
+use crate::level::builder::{spawn_chunk, ChunkEntities};
+use crate::level::{
+    BspConfig, CaveRules, Chunk, Dungeon, LevelGrid, PlacedEntitySpawners, Tile, WallStyle,
+};
+use crate::player::Player;
+use crate::seed::WorldSeed;
+
+/// How many tiles across the levels we generate are
+const GENERATED_SIZE: usize = 40;
+/// Any pocket of our caves smaller than this is filled in
+const MIN_CAVE_SIZE: usize = 8;
+
+/// A single level, either hand made in our assets folder or generated from our `LevelSource`
+#[derive(Clone, Debug, PartialEq, TypeUuid)]
+#[uuid = "bdffe0f5-d669-46b0-b44e-154a1c9d9413"]
+pub struct Level {
+    pub chunk: Chunk,
+    /// anything placed on top of our tiles for gameplay to pick up
+    pub entities: Vec<PlacedEntity>,
+    /// the gltf whose markers spawn things into our level, i.e. `models.gltf`
+    pub markers: Option<String>,
+}
+
+/// Something placed on a tile of our level, i.e. a `"chest"` or a `"trigger"`. We spawn these with
+/// our `PlacedEntitySpawners` and they keep this as a component.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlacedEntity {
+    pub kind: String,
+    pub tile: (usize, usize),
+}
+
+impl Level {
+    pub fn new(chunk: Chunk) -> Level {
+        Level {
+            chunk,
+            entities: vec![],
+            markers: None,
+        }
+    }
+
+    /// The first spawn tile in our level
+    pub fn spawn(&self) -> Option<(usize, usize)> {
+        self.chunk
+            .cells()
+            .find(|(_, _, tile)| *tile == Tile::Spawn)
+            .map(|(x, y, _)| (x, y))
+    }
+
+    /// The grid tile of our `(0, 0)` cell, we center our levels on tile `(0, 0)`
+    pub fn offset(&self) -> (i32, i32) {
+        (
+            -((self.chunk.width() / 2) as i32),
+            -((self.chunk.height() / 2) as i32),
+        )
+    }
+}
+
+/// Where the world we play in comes from
+pub enum LevelSource {
+    /// an endless world of generated caves
+    Streamed,
+    /// a single cave grown from our `WorldSeed`
+    Caves,
+    /// rooms joined by corridors grown from our `WorldSeed`, starting in the first room
+    Dungeon,
+    /// a level file from our assets folder, i.e. `levels/arena.level`
+    Asset(String),
+}
+
+impl Default for LevelSource {
+    fn default() -> Self {
+        LevelSource::Streamed
+    }
+}
+
+impl LevelSource {
+    /// Our `--level <level>` argument, either `streamed`, `caves`, `dungeon` or the path of a level
+    /// in our assets folder. Without one we play our streamed world.
+    pub fn from_args() -> Self {
+        match find_arg(std::env::args(), "--level") {
+            Some(Ok(level)) => level.parse().unwrap(),
+            Some(Err(e)) => {
+                log::warn!("Ignoring our --level: {:?}", e);
+                LevelSource::default()
+            }
+            None => LevelSource::default(),
+        }
+    }
+
+    /// Build our level from `seed` if we generate it ourselves
+    pub fn generate(&self, seed: WorldSeed) -> Option<Level> {
+        match self {
+            LevelSource::Caves => {
+                let mut chunk = Chunk::caves(
+                    &mut seed.rng("caves"),
+                    GENERATED_SIZE,
+                    GENERATED_SIZE,
+                    0.45,
+                    4,
+                    CaveRules::default(),
+                );
+                chunk.remove_pockets(MIN_CAVE_SIZE);
+
+                // start as close to the middle of our cave as we can
+                let middle = (GENERATED_SIZE / 2) as isize;
+                if let Some((x, y, _)) = chunk
+                    .cells()
+                    .filter(|(_, _, tile)| tile.is_walkable())
+                    .min_by_key(|(x, y, _)| {
+                        let (dx, dy) = (*x as isize - middle, *y as isize - middle);
+                        dx * dx + dy * dy
+                    })
+                {
+                    chunk.set(x, y, Tile::Spawn);
+                }
+
+                Some(Level::new(chunk))
+            }
+            LevelSource::Dungeon => {
+                // our default config is always valid
+                let Dungeon { mut chunk, rooms } = Chunk::bsp(
+                    &mut seed.rng("dungeon"),
+                    GENERATED_SIZE,
+                    GENERATED_SIZE,
+                    BspConfig::default(),
+                )
+                .unwrap();
+                if let Some(room) = rooms.first() {
+                    let (x, y) = room.center();
+                    chunk.set(x, y, Tile::Spawn);
+                }
+
+                Some(Level::new(chunk))
+            }
+            LevelSource::Streamed | LevelSource::Asset(_) => None,
+        }
+    }
+}
+
+impl FromStr for LevelSource {
+    type Err = Infallible;
+
+    /// Anything we don't generate ourselves is a level in our assets folder
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        Ok(match level {
+            "streamed" => LevelSource::Streamed,
+            "caves" => LevelSource::Caves,
+            "dungeon" => LevelSource::Dungeon,
+            path => LevelSource::Asset(path.to_string()),
+        })
+    }
+}
+
+/// The level we're playing, along with what we spawned for it once it's loaded or generated
+pub struct CurrentLevel {
+    pub handle: Handle<Level>,
+    /// the grid we last built, so we know what changed when our asset is modified
+    spawned: Option<SpawnedLevel>,
+    /// counts up every time our spawned chunk changes
+    generation: usize,
+}
+
+struct SpawnedLevel {
+    chunk: Chunk,
+    offset: (i32, i32),
+    entities: ChunkEntities,
+    /// the entities we placed on our level, along with what we spawned for them
+    placed: Vec<PlacedEntity>,
+    placed_entities: Vec<Entity>,
+}
+
+impl CurrentLevel {
+    pub fn new(handle: Handle<Level>) -> CurrentLevel {
+        CurrentLevel {
+            handle,
+            spawned: None,
+            generation: 0,
+        }
+    }
+
+    /// The chunk we've spawned and the grid tile of its `(0, 0)` cell, once we're loaded
+    pub fn chunk(&self) -> Option<(&Chunk, (i32, i32))> {
+        self.spawned
+            .as_ref()
+            .map(|spawned| (&spawned.chunk, spawned.offset))
+    }
+
+    /// Changes whenever our chunk does, so anything worked out from it knows when to start over
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+pub fn run_streamed(source: Res<LevelSource>) -> ShouldRun {
+    match *source {
+        LevelSource::Streamed => ShouldRun::Yes,
+        LevelSource::Caves | LevelSource::Dungeon | LevelSource::Asset(_) => ShouldRun::No,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn level_spawn_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    grid: Res<LevelGrid>,
+    wall_style: Res<WallStyle>,
+    spawners: Res<PlacedEntitySpawners>,
+    mut level_events: EventReader<AssetEvent<Level>>,
+    levels: Res<Assets<Level>>,
+    current: Option<ResMut<CurrentLevel>>,
+    mut player_query: Query<&mut RigidBodyPosition, With<Player>>,
+) {
+    let mut current = match current {
+        Some(current) => current,
+        None => return,
+    };
+
+    let current = &mut *current;
+    for event in level_events.iter() {
+        match event {
+            AssetEvent::Created { handle } => {
+                if *handle != current.handle || current.spawned.is_some() {
+                    continue;
+                }
+                let level = levels.get(handle).unwrap();
+
+                let gltf_handle = asset_server.load("models.gltf");
+                let entities = spawn_chunk(
+                    &mut commands,
+                    &gltf_handle,
+                    &grid,
+                    *wall_style,
+                    &level.chunk,
+                    level.offset(),
+                    |_, _| None,
+                );
+                let placed_entities = spawners.spawn(&mut commands, &grid, &gltf_handle, level);
+                current.spawned = Some(SpawnedLevel {
+                    chunk: level.chunk.clone(),
+                    offset: level.offset(),
+                    entities,
+                    placed: level.entities.clone(),
+                    placed_entities,
+                });
+                current.generation += 1;
+
+                // drop our player onto the spawn
+                if let Some((x, z)) = level.spawn() {
+                    let (offset_x, offset_z) = level.offset();
+                    let spawn = grid.tile_to_world((offset_x + x as i32, offset_z + z as i32))
+                        + Vec3::new(0., 5., 0.);
+                    for mut position in player_query.iter_mut() {
+                        position.position = Isometry::translation(spawn.x, spawn.y, spawn.z);
+                        position.next_position = position.position;
+                    }
+                }
+            }
+            AssetEvent::Modified { handle } => {
+                if *handle != current.handle {
+                    continue;
+                }
+                let level = levels.get(handle).unwrap();
+
+                // only rebuild what changed and leave our player wherever they are
+                if let Some(spawned) = &mut current.spawned {
+                    let gltf_handle = asset_server.load("models.gltf");
+                    spawned.entities.update(
+                        &mut commands,
+                        &gltf_handle,
+                        &spawned.chunk,
+                        &level.chunk,
+                        level.offset(),
+                    );
+
+                    // our placed entities only need respawning if they've moved
+                    let floors_moved = level.entities.iter().any(|placed| {
+                        let (x, z) = placed.tile;
+                        spawned.chunk.elevation(x, z) != level.chunk.elevation(x, z)
+                    });
+                    if spawned.placed != level.entities
+                        || spawned.offset != level.offset()
+                        || floors_moved
+                    {
+                        for entity in spawned.placed_entities.drain(..) {
+                            commands.entity(entity).despawn_recursive();
+                        }
+                        spawned.placed_entities =
+                            spawners.spawn(&mut commands, &grid, &gltf_handle, level);
+                        spawned.placed = level.entities.clone();
+                    }
+
+                    let resized = spawned.chunk.width() != level.chunk.width()
+                        || spawned.chunk.height() != level.chunk.height();
+                    if resized || spawned.chunk.changed_cells(&level.chunk).next().is_some() {
+                        current.generation += 1;
+                    }
+                    spawned.chunk = level.chunk.clone();
+                    spawned.offset = level.offset();
+                }
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_come_from_their_name_or_path() {
+        assert!(matches!("streamed".parse(), Ok(LevelSource::Streamed)));
+        assert!(matches!("caves".parse(), Ok(LevelSource::Caves)));
+        assert!(matches!("dungeon".parse(), Ok(LevelSource::Dungeon)));
+        assert!(matches!(
+            "levels/arena.level".parse(),
+            Ok(LevelSource::Asset(path)) if path == "levels/arena.level"
+        ));
+    }
+
+    #[test]
+    fn generated_caves_start_us_inside_them() {
+        for seed in 0..4 {
+            let level = LevelSource::Caves.generate(WorldSeed(seed)).unwrap();
+            let (x, y) = level.spawn().unwrap();
+
+            let connectivity = level.chunk.connectivity();
+            assert!(connectivity.is_connected(), "seed {}", seed);
+            assert_eq!(connectivity.region_at(x, y), Some(0), "seed {}", seed);
+            assert_eq!(
+                LevelSource::Caves.generate(WorldSeed(seed)),
+                Some(level),
+                "seed {}",
+                seed
+            );
+        }
+
+        assert_eq!(LevelSource::Streamed.generate(WorldSeed(0)), None);
+    }
+
+    #[test]
+    fn generated_dungeons_start_us_in_their_first_room() {
+        for seed in 0..4 {
+            let level = LevelSource::Dungeon.generate(WorldSeed(seed)).unwrap();
+            let Dungeon { rooms, .. } = Chunk::bsp(
+                &mut WorldSeed(seed).rng("dungeon"),
+                GENERATED_SIZE,
+                GENERATED_SIZE,
+                BspConfig::default(),
+            )
+            .unwrap();
+
+            assert_eq!(level.spawn(), Some(rooms[0].center()), "seed {}", seed);
+            assert!(level.chunk.connectivity().is_connected(), "seed {}", seed);
+        }
+    }
+}
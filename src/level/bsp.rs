@@ -0,0 +1,243 @@
+use anyhow::bail;
+use rand::Rng;
+
+use crate::level::{Chunk, Tile};
+
+/// Tuning for our binary space partition dungeon generator, both of our sizes must be at least 1
+#[derive(Clone, Copy, Debug)]
+pub struct BspConfig {
+    /// we never split a partition into pieces smaller than this
+    pub min_leaf_size: usize,
+    /// the smallest room we'll carve out of a leaf
+    pub min_room_size: usize,
+}
+
+impl BspConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.min_leaf_size == 0 {
+            bail!("our min_leaf_size must be at least 1");
+        }
+        if self.min_room_size == 0 {
+            bail!("our min_room_size must be at least 1");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for BspConfig {
+    fn default() -> Self {
+        BspConfig {
+            min_leaf_size: 6,
+            min_room_size: 3,
+        }
+    }
+}
+
+/// A rectangular room carved out of our chunk, `min` and `max` are inclusive
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Room {
+    pub id: usize,
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+}
+
+impl Room {
+    pub fn center(&self) -> (usize, usize) {
+        ((self.min.0 + self.max.0) / 2, (self.min.1 + self.max.1) / 2)
+    }
+}
+
+/// The output of our BSP generator, our grid along with the rooms we carved into it
+pub struct Dungeon {
+    pub chunk: Chunk,
+    pub rooms: Vec<Room>,
+}
+
+/// A partition of our chunk, `min` is inclusive and `max` is exclusive
+#[derive(Clone, Copy)]
+struct Leaf {
+    min: (usize, usize),
+    max: (usize, usize),
+}
+
+impl Leaf {
+    fn width(&self) -> usize {
+        self.max.0 - self.min.0
+    }
+
+    fn height(&self) -> usize {
+        self.max.1 - self.min.1
+    }
+}
+
+impl Chunk {
+    /// Recursively split our chunk, carve a room into every leaf and join siblings with corridors
+    pub fn bsp<R: Rng>(
+        rng: &mut R,
+        width: usize,
+        height: usize,
+        config: BspConfig,
+    ) -> anyhow::Result<Dungeon> {
+        config.validate()?;
+
+        let mut dungeon = Dungeon {
+            chunk: Chunk::new(width, height, Tile::Wall),
+            rooms: vec![],
+        };
+
+        // leave a ring of wall around the outside of our chunk
+        if width > 2 && height > 2 {
+            let root = Leaf {
+                min: (1, 1),
+                max: (width - 1, height - 1),
+            };
+            dungeon.split(rng, root, &config);
+        }
+
+        Ok(dungeon)
+    }
+}
+
+impl Dungeon {
+    /// Split our leaf until it's too small, returning the ids of the rooms we carved inside of it
+    fn split<R: Rng>(&mut self, rng: &mut R, leaf: Leaf, config: &BspConfig) -> Vec<usize> {
+        let can_split_x = leaf.width() >= config.min_leaf_size * 2;
+        let can_split_y = leaf.height() >= config.min_leaf_size * 2;
+
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => return self.carve_room(rng, leaf, config).into_iter().collect(),
+            (true, false) => true,
+            (false, true) => false,
+            // prefer to cut across the long side so our rooms stay roughly square
+            (true, true) if leaf.width() * 4 > leaf.height() * 5 => true,
+            (true, true) if leaf.height() * 4 > leaf.width() * 5 => false,
+            (true, true) => rng.gen_bool(0.5),
+        };
+
+        let (first, second) = if split_x {
+            let at = rng
+                .gen_range(leaf.min.0 + config.min_leaf_size..=leaf.max.0 - config.min_leaf_size);
+            (
+                Leaf {
+                    max: (at, leaf.max.1),
+                    ..leaf
+                },
+                Leaf {
+                    min: (at, leaf.min.1),
+                    ..leaf
+                },
+            )
+        } else {
+            let at = rng
+                .gen_range(leaf.min.1 + config.min_leaf_size..=leaf.max.1 - config.min_leaf_size);
+            (
+                Leaf {
+                    max: (leaf.max.0, at),
+                    ..leaf
+                },
+                Leaf {
+                    min: (leaf.min.0, at),
+                    ..leaf
+                },
+            )
+        };
+
+        let mut first_rooms = self.split(rng, first, config);
+        let second_rooms = self.split(rng, second, config);
+
+        // join our two halves together through a random room on each side
+        if !first_rooms.is_empty() && !second_rooms.is_empty() {
+            let from = self.rooms[first_rooms[rng.gen_range(0..first_rooms.len())]].center();
+            let to = self.rooms[second_rooms[rng.gen_range(0..second_rooms.len())]].center();
+            self.carve_corridor(rng, from, to);
+        }
+
+        first_rooms.extend(second_rooms);
+        first_rooms
+    }
+
+    /// Carve a randomly sized room into our leaf, keeping a wall between it and its neighbours
+    fn carve_room<R: Rng>(&mut self, rng: &mut R, leaf: Leaf, config: &BspConfig) -> Option<usize> {
+        let max_width = leaf.width().checked_sub(1)?;
+        let max_height = leaf.height().checked_sub(1)?;
+        if max_width < config.min_room_size || max_height < config.min_room_size {
+            return None;
+        }
+
+        let width = rng.gen_range(config.min_room_size..=max_width);
+        let height = rng.gen_range(config.min_room_size..=max_height);
+        let x = rng.gen_range(leaf.min.0..=leaf.max.0 - 1 - width);
+        let y = rng.gen_range(leaf.min.1..=leaf.max.1 - 1 - height);
+
+        let room = Room {
+            id: self.rooms.len(),
+            min: (x, y),
+            max: (x + width - 1, y + height - 1),
+        };
+        for y in room.min.1..=room.max.1 {
+            for x in room.min.0..=room.max.0 {
+                self.chunk.set(x, y, Tile::Floor);
+            }
+        }
+        self.rooms.push(room);
+
+        Some(room.id)
+    }
+
+    /// Carve an L shaped corridor between two points, randomly picking which leg goes first
+    fn carve_corridor<R: Rng>(&mut self, rng: &mut R, from: (usize, usize), to: (usize, usize)) {
+        let corner = if rng.gen_bool(0.5) {
+            (to.0, from.1)
+        } else {
+            (from.0, to.1)
+        };
+
+        for &(start, end) in [(from, corner), (corner, to)].iter() {
+            for y in start.1.min(end.1)..=start.1.max(end.1) {
+                for x in start.0.min(end.0)..=start.0.max(end.0) {
+                    self.chunk.set(x, y, Tile::Floor);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    #[test]
+    fn empty_sizes_are_rejected() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for config in [
+            BspConfig {
+                min_leaf_size: 0,
+                ..Default::default()
+            },
+            BspConfig {
+                min_room_size: 0,
+                ..Default::default()
+            },
+        ]
+        .iter()
+        {
+            assert!(Chunk::bsp(&mut rng, 32, 32, *config).is_err());
+        }
+    }
+
+    #[test]
+    fn smallest_sizes_carve_rooms() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let config = BspConfig {
+            min_leaf_size: 2,
+            min_room_size: 1,
+        };
+        let dungeon = Chunk::bsp(&mut rng, 16, 16, config).unwrap();
+
+        assert!(!dungeon.rooms.is_empty());
+    }
+}
@@ -0,0 +1,885 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::na::DMatrix;
+
+use crate::level::{
+    Chunk, Direction, LevelGrid, Tile, TileCollider, TileSensor, WallOutline, WallStyle,
+};
+use crate::mesh_loader::{MissingMesh, SpawnMeshAsChildCommands};
+
+/// How thick our wall mesh is
+const WALL_THICKNESS: f32 = 0.1;
+/// How many times our ground heightfield samples each tile along each side. Floors at different
+/// elevations meet in a riser this fraction of a tile wide.
+const GROUND_SAMPLES: usize = 4;
+/// How much we darken a floor's corner when it's boxed in by walls
+const AMBIENT_OCCLUSION: f32 = 0.5;
+
+/// Which of a cell's two edges a wall sits along, every cell owns the walls on its east and south
+/// edges so each wall is only spawned once
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+enum Edge {
+    East,
+    South,
+}
+
+impl Edge {
+    fn offset(self) -> (usize, usize) {
+        match self {
+            Edge::East => (1, 0),
+            Edge::South => (0, 1),
+        }
+    }
+}
+
+/// The pieces that join our walls where they meet. Each is modelled with an arm running north
+/// (-z) and then going clockwise, i.e. our corner runs north and east.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WallJoint {
+    /// north
+    End,
+    /// north and east
+    Corner,
+    /// north, east and south
+    T,
+    Cross,
+}
+
+impl WallJoint {
+    const ALL: [WallJoint; 4] = [
+        WallJoint::End,
+        WallJoint::Corner,
+        WallJoint::T,
+        WallJoint::Cross,
+    ];
+
+    fn mesh_name(self) -> &'static str {
+        match self {
+            WallJoint::End => "wall_end",
+            WallJoint::Corner => "wall_corner",
+            WallJoint::T => "wall_t",
+            WallJoint::Cross => "wall_cross",
+        }
+    }
+
+    /// Our arms as a mask of north, east, south, west
+    fn mask(self) -> u8 {
+        match self {
+            WallJoint::End => 0b0001,
+            WallJoint::Corner => 0b0011,
+            WallJoint::T => 0b0111,
+            WallJoint::Cross => 0b1111,
+        }
+    }
+
+    /// Find the joint and the number of quarter turns that match the arms in `mask`, we turn
+    /// counter clockwise so every turn moves our north arm west
+    fn from_mask(mask: u8) -> Option<(WallJoint, u32)> {
+        WallJoint::ALL.iter().find_map(|joint| {
+            let mut rotated = joint.mask();
+            for turns in 0..4 {
+                if rotated == mask {
+                    return Some((*joint, turns));
+                }
+                rotated = ((rotated << 3) | (rotated >> 1)) & 0b1111;
+            }
+            None
+        })
+    }
+}
+
+/// The wall along the first arm of a joint at the south east corner of `x`, `z` after `rotation`
+/// counter clockwise quarter turns, i.e. north, west, south and then east
+fn first_arm(x: usize, z: usize, rotation: u32) -> (usize, usize, Edge) {
+    match rotation % 4 {
+        0 => (x, z, Edge::East),
+        1 => (x, z, Edge::South),
+        2 => (x, z + 1, Edge::East),
+        _ => (x + 1, z, Edge::South),
+    }
+}
+
+/// Everything we spawned for a chunk, so we can rebuild individual cells when it changes
+pub struct ChunkEntities {
+    /// every entity below is a child of our root
+    pub root: Entity,
+    grid: LevelGrid,
+    style: WallStyle,
+    /// the tile of our grid our `(0, 0)` cell sits on
+    offset: (i32, i32),
+    /// the heightfield we stand on, if we have any cells at all
+    ground: Option<Entity>,
+    /// the static body holding all of our block walls, if we have any
+    body: Option<Entity>,
+    /// one merged mesh for each of the models our chunk is built out of, plus our smooth walls
+    meshes: Vec<Entity>,
+    /// the colliders and sensors of the tiles that have them
+    floors: HashMap<(usize, usize), Entity>,
+    /// the elevation of the floor each of our walls stands on
+    walls: HashMap<(usize, usize, Edge), f32>,
+}
+
+/// Spawn the floors, walls and ground of `chunk` with its `(0, 0)` cell on the `offset` tile of our
+/// grid, parented to a single entity so the whole chunk can be despawned together.
+///
+/// `outside` looks up the cells around our chunk, from `-1` on our west and north edges to
+/// `width` and `height` on our east and south ones, so the walls along our seams line up with
+/// whatever is next to us.
+pub fn spawn_chunk<F: Fn(isize, isize) -> Option<Tile>>(
+    commands: &mut Commands,
+    gltf_handle: &Handle<Gltf>,
+    grid: &LevelGrid,
+    style: WallStyle,
+    chunk: &Chunk,
+    offset: (i32, i32),
+    outside: F,
+) -> ChunkEntities {
+    let root = commands
+        .spawn_bundle((Transform::identity(), GlobalTransform::identity()))
+        .insert("Chunk".to_string())
+        .id();
+    let mut entities = ChunkEntities {
+        root,
+        grid: *grid,
+        style,
+        offset,
+        ground: None,
+        body: None,
+        meshes: vec![],
+        floors: HashMap::new(),
+        walls: HashMap::new(),
+    };
+
+    commands.entity(root).with_children(|parent| {
+        for (x, z, _) in chunk.cells() {
+            for edge in [Edge::East, Edge::South].iter() {
+                entities.add_wall(chunk, x, z, *edge, &outside);
+            }
+            entities.spawn_floor(parent, chunk, x, z);
+        }
+
+        entities.spawn_ground(parent, chunk);
+        entities.spawn_body(parent);
+        entities.spawn_meshes(parent, gltf_handle, chunk, &outside);
+    });
+
+    entities
+}
+
+impl ChunkEntities {
+    /// Rebuild our chunk as `new`, only respawning the floors and walls of cells that differ from
+    /// `old`. If our dimensions changed we just start over from scratch at `offset`.
+    pub fn update(
+        &mut self,
+        commands: &mut Commands,
+        gltf_handle: &Handle<Gltf>,
+        old: &Chunk,
+        new: &Chunk,
+        offset: (i32, i32),
+    ) {
+        if old.width() != new.width() || old.height() != new.height() || offset != self.offset {
+            let (grid, style) = (self.grid, self.style);
+            commands.entity(self.root).despawn_recursive();
+            *self = spawn_chunk(commands, gltf_handle, &grid, style, new, offset, |_, _| {
+                None
+            });
+            return;
+        }
+
+        let changed = old.changed_cells(new).collect::<Vec<_>>();
+        if changed.is_empty() {
+            return;
+        }
+
+        // a changed cell touches its own floor and all 4 of the walls around it
+        let mut floors = vec![];
+        let mut walls = vec![];
+        for (x, z) in changed {
+            floors.push((x, z));
+            walls.push((x, z, Edge::East));
+            walls.push((x, z, Edge::South));
+            if x > 0 {
+                walls.push((x - 1, z, Edge::East));
+            }
+            if z > 0 {
+                walls.push((x, z - 1, Edge::South));
+            }
+        }
+
+        for cell in floors.iter() {
+            if let Some(entity) = self.floors.remove(cell) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        for wall in walls.iter() {
+            self.walls.remove(wall);
+        }
+
+        // our ground, walls and meshes are all shared by the whole chunk so they need rebuilding
+        // from scratch
+        if let Some(ground) = self.ground {
+            commands.entity(ground).despawn_recursive();
+        }
+        if let Some(body) = self.body {
+            commands.entity(body).despawn_recursive();
+        }
+        for entity in self.meshes.drain(..) {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        let root = self.root;
+        commands.entity(root).with_children(|parent| {
+            for (x, z, edge) in walls {
+                self.add_wall(new, x, z, edge, &|_, _| None);
+            }
+            for (x, z) in floors {
+                self.spawn_floor(parent, new, x, z);
+            }
+            self.spawn_ground(parent, new);
+            self.spawn_body(parent);
+            self.spawn_meshes(parent, gltf_handle, new, &|_, _| None);
+        });
+    }
+
+    /// Spawn our ground as a heightfield reaching right to the edges of our chunk, following our
+    /// floors up their ramps and down into their pits
+    fn spawn_ground(&mut self, parent: &mut ChildBuilder, chunk: &Chunk) {
+        if chunk.width() == 0 || chunk.height() == 0 {
+            self.ground = None;
+            return;
+        }
+
+        let size = self.grid.tile_size;
+        let (columns, rows, heights) = ground_heights(chunk);
+        // our heightfield is centered on its position, with its rows running along z
+        let center = self.cell_position(0, 0)
+            + Vec3::new(chunk.width() as f32 - 1., 0., chunk.height() as f32 - 1.) / 2. * size;
+        let shape = ColliderShape::heightfield(
+            DMatrix::from_row_slice(rows, columns, &heights),
+            Vector::new(
+                chunk.width() as f32 * size,
+                size,
+                chunk.height() as f32 * size,
+            ),
+        );
+
+        self.ground = Some(
+            parent
+                .spawn_bundle(ColliderBundle {
+                    shape,
+                    position: center.into(),
+                    ..Default::default()
+                })
+                .insert("Ground".to_string())
+                .id(),
+        );
+    }
+
+    /// Spawn a single static body for our walls, every run of walls along a row or column at the
+    /// same elevation becomes one cuboid of a compound collider
+    fn spawn_body(&mut self, parent: &mut ChildBuilder) {
+        // our smooth walls bring their own collider along with their mesh
+        if self.style == WallStyle::Smooth {
+            self.body = None;
+            return;
+        }
+
+        let size = self.grid.tile_size;
+        let origin = self.grid.tile_to_world(self.offset);
+        let cuboid = |center: Vec3, half_extents: Vec3| {
+            let center: Vector<Real> = (origin + center * size).into();
+            let half_extents = half_extents * size;
+            (
+                Isometry::from(center),
+                ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            )
+        };
+
+        let mut shapes = vec![];
+        for edge in [Edge::East, Edge::South].iter() {
+            // (line, position along our line, elevation) of every wall on this edge, sorted so
+            // runs are adjacent
+            let mut walls = self
+                .walls
+                .iter()
+                .filter(|((_, _, wall_edge), _)| wall_edge == edge)
+                .map(|((x, z, _), elevation)| match edge {
+                    Edge::East => (*x, *z, *elevation),
+                    Edge::South => (*z, *x, *elevation),
+                })
+                .collect::<Vec<_>>();
+            walls.sort_unstable_by_key(|(line, along, _)| (*line, *along));
+
+            let mut index = 0;
+            while index < walls.len() {
+                let (line, start, elevation) = walls[index];
+                let mut end = start;
+                while index + 1 < walls.len() && walls[index + 1] == (line, end + 1, elevation) {
+                    index += 1;
+                    end += 1;
+                }
+                index += 1;
+
+                // our walls sit on the far edge of their cells
+                let along = (start + end) as f32 / 2.;
+                let length = (end - start + 1) as f32 / 2.;
+                let y = elevation + 0.5;
+                shapes.push(match edge {
+                    Edge::East => cuboid(
+                        Vec3::new(line as f32 + 0.5, y, along),
+                        Vec3::new(WALL_THICKNESS / 2., 0.5, length),
+                    ),
+                    Edge::South => cuboid(
+                        Vec3::new(along, y, line as f32 + 0.5),
+                        Vec3::new(length, 0.5, WALL_THICKNESS / 2.),
+                    ),
+                });
+            }
+        }
+
+        // a compound shape needs something in it
+        if shapes.is_empty() {
+            self.body = None;
+            return;
+        }
+
+        self.body = Some(
+            parent
+                .spawn_bundle(RigidBodyBundle {
+                    body_type: RigidBodyType::Static,
+                    ..Default::default()
+                })
+                .insert_bundle(ColliderBundle {
+                    shape: ColliderShape::compound(shapes),
+                    ..Default::default()
+                })
+                .insert("Walls".to_string())
+                .id(),
+        );
+    }
+
+    /// Merge every floor and wall of our chunk into one mesh per model
+    fn spawn_meshes<F: Fn(isize, isize) -> Option<Tile>>(
+        &mut self,
+        parent: &mut ChildBuilder,
+        gltf_handle: &Handle<Gltf>,
+        chunk: &Chunk,
+        outside: &F,
+    ) {
+        let size = self.grid.tile_size;
+        let mut instances: HashMap<&str, Vec<Transform>> = HashMap::new();
+        let mut occlusion: HashMap<&str, Vec<[f32; 4]>> = HashMap::new();
+
+        for (x, z, _) in chunk.cells() {
+            if let Some(mesh_name) = chunk.floor_mesh(x, z) {
+                let mut transform = floor_transform(chunk, x, z);
+                transform.translation = self.cell_position(x, z) + transform.translation * size;
+                transform.scale *= size;
+                instances.entry(mesh_name).or_default().push(transform);
+                occlusion
+                    .entry(mesh_name)
+                    .or_default()
+                    .push(floor_occlusion(chunk, x, z, outside));
+            }
+        }
+
+        if self.style == WallStyle::Smooth {
+            self.spawn_outline(parent, gltf_handle, chunk, outside);
+        }
+
+        let (block_walls, joints) = match self.style {
+            WallStyle::Blocks => self.wall_pieces(),
+            WallStyle::Smooth => (vec![], vec![]),
+        };
+        for ((x, z, edge), elevation) in block_walls {
+            // our wall mesh runs from its origin back along -z, so step out to the far corner of
+            // our cell and rotate onto our south edge if we need to
+            let rotation = match edge {
+                Edge::East => Quat::IDENTITY,
+                Edge::South => Quat::from_rotation_y(FRAC_PI_2),
+            };
+            instances.entry("wall").or_default().push(Transform {
+                translation: self.cell_position(x, z) + Vec3::new(0.5, elevation, 0.5) * size,
+                rotation,
+                scale: Vec3::splat(size),
+            });
+        }
+
+        for (x, z, joint, rotation, elevation) in joints {
+            instances
+                .entry(joint.mesh_name())
+                .or_default()
+                .push(Transform {
+                    translation: self.cell_position(x, z) + Vec3::new(0.5, elevation, 0.5) * size,
+                    rotation: Quat::from_rotation_y(FRAC_PI_2 * rotation as f32),
+                    scale: Vec3::splat(size),
+                });
+        }
+
+        for (mesh_name, transforms) in instances {
+            // our joints are optional, without them we draw the straight wall they stand in for
+            let missing = if WallJoint::ALL
+                .iter()
+                .any(|joint| joint.mesh_name() == mesh_name)
+            {
+                MissingMesh::Fallback("wall".to_string())
+            } else {
+                MissingMesh::Panic
+            };
+
+            let entity = parent
+                .spawn_bundle((Transform::identity(), GlobalTransform::identity()))
+                .insert(mesh_name.to_string())
+                .with_children(|builder| {
+                    builder.spawn_merged_mesh(
+                        gltf_handle.clone(),
+                        mesh_name,
+                        missing,
+                        transforms,
+                        occlusion.remove(mesh_name),
+                    );
+                })
+                .id();
+            self.meshes.push(entity);
+        }
+    }
+
+    /// Spawn our walls as one smooth outline around our blocking cells, drawn with the material of
+    /// our wall model and with a matching static collider
+    fn spawn_outline<F: Fn(isize, isize) -> Option<Tile>>(
+        &mut self,
+        parent: &mut ChildBuilder,
+        gltf_handle: &Handle<Gltf>,
+        chunk: &Chunk,
+        outside: &F,
+    ) {
+        let WallOutline { mesh, collider } = chunk.wall_outline(self.grid.tile_size, 1., outside);
+        let collider = match collider {
+            Some(collider) => collider,
+            None => return,
+        };
+
+        let origin = self.cell_position(0, 0);
+        let entity = parent
+            .spawn_bundle(ColliderBundle {
+                shape: collider,
+                position: origin.into(),
+                ..Default::default()
+            })
+            .insert_bundle((
+                Transform::from_translation(origin),
+                GlobalTransform::identity(),
+            ))
+            .insert("Walls".to_string())
+            .with_children(|builder| {
+                builder.spawn_custom_mesh(gltf_handle.clone(), "wall", mesh);
+            })
+            .id();
+        self.meshes.push(entity);
+    }
+
+    /// Split our walls into the straight walls we draw as is and the joints that replace them.
+    ///
+    /// Every joint is modelled as the straight wall along its first arm with the junction at its
+    /// origin, so it stands in for that wall and falls back to it when our gltf doesn't have the
+    /// joint. A wall can only stand in for one joint, so when the joints at both of its ends want
+    /// it the one with the most arms wins and the other end is left as a plain wall.
+    #[allow(clippy::type_complexity)]
+    fn wall_pieces(
+        &self,
+    ) -> (
+        Vec<((usize, usize, Edge), f32)>,
+        Vec<(usize, usize, WallJoint, u32, f32)>,
+    ) {
+        let mut joints = self.wall_joints();
+        joints.sort_by_key(|(_, _, joint, _, _)| std::cmp::Reverse(joint.mask().count_ones()));
+
+        let mut replaced = HashSet::new();
+        let joints = joints
+            .into_iter()
+            .filter_map(|(x, z, joint, rotation, _)| {
+                let wall = first_arm(x, z, rotation);
+                let elevation = *self.walls.get(&wall)?;
+                if replaced.insert(wall) {
+                    Some((x, z, joint, rotation, elevation))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let walls = self
+            .walls
+            .iter()
+            .filter(|(wall, _)| !replaced.contains(*wall))
+            .map(|(wall, elevation)| (*wall, *elevation))
+            .collect();
+
+        (walls, joints)
+    }
+
+    /// Look at the walls meeting at the south east corner of every cell and pick the piece that
+    /// joins them, along with how many quarter turns it needs. Straight runs don't need anything.
+    fn wall_joints(&self) -> Vec<(usize, usize, WallJoint, u32, f32)> {
+        let mut corners = self
+            .walls
+            .keys()
+            .flat_map(|(x, z, edge)| match edge {
+                // our east walls also touch the corner north of us, our south walls the one west
+                Edge::East => vec![(*x as isize, *z as isize), (*x as isize, *z as isize - 1)],
+                Edge::South => vec![(*x as isize, *z as isize), (*x as isize - 1, *z as isize)],
+            })
+            .filter(|(x, z)| *x >= 0 && *z >= 0)
+            .map(|(x, z)| (x as usize, z as usize))
+            .collect::<Vec<_>>();
+        corners.sort_unstable();
+        corners.dedup();
+
+        corners
+            .into_iter()
+            .filter_map(|(x, z)| {
+                let wall = |key: (usize, usize, Edge)| self.walls.get(&key);
+                let arms = [
+                    wall((x, z, Edge::East)),
+                    wall((x + 1, z, Edge::South)),
+                    wall((x, z + 1, Edge::East)),
+                    wall((x, z, Edge::South)),
+                ];
+                let mask = arms
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, arm)| arm.is_some())
+                    .fold(0u8, |mask, (direction, _)| mask | 1 << direction);
+                let elevation = arms
+                    .iter()
+                    .flatten()
+                    .fold(f32::MIN, |highest, elevation| highest.max(**elevation));
+
+                WallJoint::from_mask(mask)
+                    .map(|(joint, rotation)| (x, z, joint, rotation, elevation))
+            })
+            .collect()
+    }
+
+    fn add_wall<F: Fn(isize, isize) -> Option<Tile>>(
+        &mut self,
+        chunk: &Chunk,
+        x: usize,
+        z: usize,
+        edge: Edge,
+        outside: &F,
+    ) {
+        let (dx, dz) = edge.offset();
+        let tile = chunk.get(x, z);
+        let neighbour = chunk
+            .get(x + dx, z + dz)
+            .or_else(|| outside((x + dx) as isize, (z + dz) as isize));
+
+        if let (Some(tile), Some(neighbour)) = (tile, neighbour) {
+            if tile.blocks() != neighbour.blocks() {
+                // stand our wall on the floor we can walk on
+                let floor = if tile.blocks() {
+                    (x + dx, z + dz)
+                } else {
+                    (x, z)
+                };
+                let elevation = chunk.elevation(floor.0, floor.1).unwrap_or_default();
+                self.walls.insert((x, z, edge), elevation);
+            }
+        }
+    }
+
+    /// Fill the whole cell above our floor with a collider or sensor if our tile needs one
+    fn spawn_floor(&mut self, parent: &mut ChildBuilder, chunk: &Chunk, x: usize, z: usize) {
+        let tile = match chunk.get(x, z) {
+            Some(tile) => tile,
+            None => return,
+        };
+        let collider_type = match tile.collider() {
+            TileCollider::None => return,
+            TileCollider::Solid => ColliderType::Solid,
+            TileCollider::Sensor => ColliderType::Sensor,
+        };
+
+        let size = self.grid.tile_size;
+        let elevation = chunk.elevation(x, z).unwrap_or_default();
+        let position: Vector<Real> =
+            (self.cell_position(x, z) + Vec3::Y * (elevation + 0.5) * size).into();
+        let mut tile_commands = parent.spawn_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(0.5 * size, 0.5 * size, 0.5 * size),
+            collider_type,
+            position: Isometry::from(position).into(),
+            flags: ActiveEvents::INTERSECTION_EVENTS.into(),
+            ..Default::default()
+        });
+        tile_commands.insert(format!("{:?}", tile));
+        if tile.collider() == TileCollider::Sensor {
+            tile_commands.insert(TileSensor(tile));
+        }
+
+        self.floors.insert((x, z), tile_commands.id());
+    }
+
+    fn cell_position(&self, x: usize, z: usize) -> Vec3 {
+        let tile = (self.offset.0 + x as i32, self.offset.1 + z as i32);
+        self.grid.tile_to_world(tile)
+    }
+}
+
+/// How lit each corner of the floor at `x`, `z` is, going clockwise from the north west. Like
+/// voxel ambient occlusion every corner looks at the 2 cells beside it and the 1 diagonally across
+/// from it, a corner tucked between 2 walls is as dark as it gets.
+fn floor_occlusion<F: Fn(isize, isize) -> Option<Tile>>(
+    chunk: &Chunk,
+    x: usize,
+    z: usize,
+    outside: &F,
+) -> [f32; 4] {
+    let blocks = |dx: isize, dz: isize| {
+        let (nx, nz) = (x as isize + dx, z as isize + dz);
+        let tile = if nx < 0 || nz < 0 {
+            None
+        } else {
+            chunk.get(nx as usize, nz as usize)
+        };
+        tile.or_else(|| outside(nx, nz))
+            .map_or(false, |tile| tile.blocks())
+    };
+
+    let mut corners = [1.; 4];
+    for (corner, (dx, dz)) in [(-1, -1), (1, -1), (1, 1), (-1, 1)].iter().enumerate() {
+        let (side_x, side_z, across) = (blocks(*dx, 0), blocks(0, *dz), blocks(*dx, *dz));
+        let walls = if side_x && side_z {
+            3
+        } else {
+            side_x as u8 + side_z as u8 + across as u8
+        };
+        corners[corner] = 1. - AMBIENT_OCCLUSION * walls as f32 / 3.;
+    }
+
+    corners
+}
+
+/// Where the floor mesh at `x`, `z` sits relative to its cell, in tiles. Ramps are tilted and
+/// stretched to reach from one side of their cell to the other.
+fn floor_transform(chunk: &Chunk, x: usize, z: usize) -> Transform {
+    let elevation = chunk.elevation(x, z).unwrap_or_default();
+    let direction = match chunk.ramp(x, z) {
+        Some(direction) => direction,
+        None => return Transform::from_translation(Vec3::Y * elevation),
+    };
+
+    // our ramp rises by `rise` from the middle of one side of our cell to the other
+    let (dx, dz) = direction.offset();
+    let far_side = (0.5 + dx as f32 / 2., 0.5 + dz as f32 / 2.);
+    let rise = chunk.floor_height(x, z, far_side).unwrap_or(elevation) - elevation;
+    let mut scale = Vec3::ONE;
+    match direction {
+        Direction::East | Direction::West => scale.x = (1. + rise * rise).sqrt(),
+        Direction::North | Direction::South => scale.z = (1. + rise * rise).sqrt(),
+    }
+
+    let climb = Vec3::new(dx as f32, 0., dz as f32);
+    Transform {
+        translation: Vec3::Y * (elevation + rise / 2.),
+        rotation: Quat::from_axis_angle(climb.cross(Vec3::Y), rise.atan()),
+        scale,
+    }
+}
+
+/// The heights of our ground under `chunk` in tiles, as `(columns, rows, heights)` with
+/// `GROUND_SAMPLES` samples across every tile from the north west corner of our chunk to its south
+/// east, row by row. A sample on the edge between floors at different elevations takes the higher
+/// one so our ground climbs in a steep riser rather than leaving a gap.
+fn ground_heights(chunk: &Chunk) -> (usize, usize, Vec<f32>) {
+    let columns = chunk.width() * GROUND_SAMPLES + 1;
+    let rows = chunk.height() * GROUND_SAMPLES + 1;
+    // the cells a sample along one side touches, with how far across each of them it is
+    let touching = |sample: usize, cells: usize| {
+        let cell = sample / GROUND_SAMPLES;
+        let across = (sample % GROUND_SAMPLES) as f32 / GROUND_SAMPLES as f32;
+        let mut touching = vec![];
+        if across == 0. && cell > 0 {
+            touching.push((cell - 1, 1.));
+        }
+        if cell < cells {
+            touching.push((cell, across));
+        }
+        touching
+    };
+
+    let mut heights = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let height = touching(row, chunk.height())
+                .into_iter()
+                .flat_map(|(z, v)| {
+                    touching(column, chunk.width())
+                        .into_iter()
+                        .filter_map(move |(x, u)| chunk.floor_height(x, z, (u, v)))
+                })
+                .fold(f32::MIN, f32::max);
+            heights.push(height);
+        }
+    }
+
+    (columns, rows, heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn with_walls(walls: &[(usize, usize, Edge)]) -> ChunkEntities {
+        ChunkEntities {
+            root: Entity::new(0),
+            grid: LevelGrid::default(),
+            style: WallStyle::Blocks,
+            offset: (0, 0),
+            ground: None,
+            body: None,
+            meshes: vec![],
+            floors: HashMap::new(),
+            walls: walls.iter().map(|wall| (*wall, 0.)).collect(),
+        }
+    }
+
+    #[test]
+    fn joints_replace_their_walls() {
+        // an L with its corner at the south east of (1, 1)
+        let entities = with_walls(&[(1, 1, Edge::East), (2, 1, Edge::South)]);
+        let (walls, joints) = entities.wall_pieces();
+
+        assert!(walls.is_empty());
+        let mut joints = joints
+            .into_iter()
+            .map(|(x, z, joint, rotation, _)| (x, z, joint, rotation))
+            .collect::<Vec<_>>();
+        joints.sort_by_key(|(x, z, _, _)| (*x, *z));
+        assert_eq!(
+            joints,
+            vec![(1, 1, WallJoint::Corner, 0), (2, 1, WallJoint::End, 1)]
+        );
+    }
+
+    #[test]
+    fn our_models_have_every_joint() {
+        let gltf: serde_json::Value =
+            serde_json::from_str(include_str!("../../assets/models.gltf")).unwrap();
+        let meshes = gltf["meshes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|mesh| mesh["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        for joint in WallJoint::ALL.iter() {
+            assert!(meshes.contains(&joint.mesh_name()), "{:?}", joint);
+        }
+    }
+
+    #[test]
+    fn every_wall_is_drawn_once() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        for _ in 0..50 {
+            let walls = (1..6)
+                .flat_map(|z| {
+                    (1..6).flat_map(move |x| vec![(x, z, Edge::East), (x, z, Edge::South)])
+                })
+                .filter(|_| rng.gen_bool(0.4))
+                .collect::<Vec<_>>();
+            let entities = with_walls(&walls);
+            let (straight, joints) = entities.wall_pieces();
+
+            let mut drawn = straight
+                .into_iter()
+                .map(|(wall, _)| wall)
+                .collect::<Vec<_>>();
+            drawn.extend(
+                joints
+                    .into_iter()
+                    .map(|(x, z, _, rotation, _)| first_arm(x, z, rotation)),
+            );
+            drawn.sort_by_key(|(x, z, edge)| (*x, *z, *edge == Edge::South));
+            let mut expected = walls.clone();
+            expected.sort_by_key(|(x, z, edge)| (*x, *z, *edge == Edge::South));
+            assert_eq!(drawn, expected);
+        }
+    }
+
+    /// The height of our ground at every sample of `row`
+    fn ground_row(chunk: &Chunk, row: usize) -> Vec<f32> {
+        let (columns, rows, heights) = ground_heights(chunk);
+        assert_eq!(columns, chunk.width() * GROUND_SAMPLES + 1);
+        assert_eq!(rows, chunk.height() * GROUND_SAMPLES + 1);
+
+        heights[row * columns..(row + 1) * columns].to_vec()
+    }
+
+    #[test]
+    fn ramps_join_their_floors_without_a_step() {
+        // a flat floor, a ramp climbing east and a raised floor
+        let mut chunk = Chunk::new(3, 2, Tile::Floor);
+        chunk.set_ramp(1, 0, Some(Direction::East));
+        chunk.set_ramp(1, 1, Some(Direction::East));
+        for z in 0..2 {
+            chunk.set_elevation(2, z, 1.);
+        }
+
+        for row in 0..=2 * GROUND_SAMPLES {
+            let heights = ground_row(&chunk, row);
+            let step = 1. / GROUND_SAMPLES as f32;
+            for pair in heights.windows(2) {
+                assert!(pair[1] >= pair[0], "{:?}", heights);
+                assert!(pair[1] - pair[0] <= step + f32::EPSILON, "{:?}", heights);
+            }
+            assert_eq!(heights[0], 0.);
+            assert_eq!(heights[heights.len() - 1], 1.);
+        }
+    }
+
+    #[test]
+    fn pits_sink_below_our_ground() {
+        let mut chunk = Chunk::new(3, 3, Tile::Floor);
+        chunk.set_elevation(1, 1, -1.);
+
+        let middle = ground_row(&chunk, GROUND_SAMPLES + GROUND_SAMPLES / 2);
+        // the floor of our pit is the floor of our pit all the way to its walls, which climb
+        // straight back up to the ground around it
+        let pit = GROUND_SAMPLES + 1..2 * GROUND_SAMPLES;
+        for (column, height) in middle.iter().enumerate() {
+            let expected = if pit.contains(&column) { -1. } else { 0. };
+            assert_eq!(*height, expected, "{:?}", middle);
+        }
+        assert!(ground_row(&chunk, 0).iter().all(|height| *height == 0.));
+    }
+
+    #[test]
+    fn ramped_floors_reach_across_their_cell() {
+        let mut chunk = Chunk::new(1, 2, Tile::Floor);
+        chunk.set_elevation(0, 0, 0.5);
+        chunk.set_ramp(0, 1, Some(Direction::North));
+
+        // we draw with our transform's matrix, which scales before it rotates
+        let matrix = floor_transform(&chunk, 0, 1).compute_matrix();
+        let south = matrix.transform_point3(Vec3::new(0., 0., 0.5));
+        let north = matrix.transform_point3(Vec3::new(0., 0., -0.5));
+        assert!(
+            (south - Vec3::new(0., 0., 0.5)).length() < 1e-5,
+            "{}",
+            south
+        );
+        assert!(
+            (north - Vec3::new(0., 0.5, -0.5)).length() < 1e-5,
+            "{}",
+            north
+        );
+        assert_eq!(floor_transform(&chunk, 0, 0).translation, Vec3::Y * 0.5);
+    }
+}
@@ -0,0 +1,232 @@
+use rand::Rng;
+
+use crate::level::{Chunk, Repair, Tile};
+
+/// Birth / survival rules for our cave automaton, indexed by the number of neighbouring walls
+#[derive(Clone, Copy, Debug)]
+pub struct CaveRules {
+    /// a floor becomes a wall when it has this many walls around it
+    pub birth: [bool; 9],
+    /// a wall stays a wall when it has this many walls around it
+    pub survival: [bool; 9],
+}
+
+impl CaveRules {
+    /// We only have 8 neighbours, so any count above 8 can never happen and is ignored
+    pub fn new(birth: &[usize], survival: &[usize]) -> CaveRules {
+        let mut rules = CaveRules {
+            birth: [false; 9],
+            survival: [false; 9],
+        };
+        for &count in birth {
+            if let Some(rule) = rules.birth.get_mut(count) {
+                *rule = true;
+            }
+        }
+        for &count in survival {
+            if let Some(rule) = rules.survival.get_mut(count) {
+                *rule = true;
+            }
+        }
+
+        rules
+    }
+}
+
+impl Default for CaveRules {
+    /// The classic B5678/S45678 rule, which smooths noise into open caverns
+    fn default() -> Self {
+        CaveRules::new(&[5, 6, 7, 8], &[4, 5, 6, 7, 8])
+    }
+}
+
+impl Chunk {
+    /// Fill our chunk with walls at `fill_ratio` and then smooth it `iterations` times into caves
+    pub fn caves<R: Rng>(
+        rng: &mut R,
+        width: usize,
+        height: usize,
+        fill_ratio: f64,
+        iterations: usize,
+        rules: CaveRules,
+    ) -> Chunk {
+        let mut chunk = Chunk::new(width, height, Tile::Floor);
+        for y in 0..height {
+            for x in 0..width {
+                chunk.set(x, y, Tile::from_floor(!rng.gen_bool(fill_ratio)));
+            }
+        }
+
+        for _ in 0..iterations {
+            chunk.smooth(rules);
+        }
+
+        // always close off our edges so nothing can walk out of the cave
+        chunk.fill_border(Tile::Wall);
+        // and join up any caves we can't walk between, our border is a wall so we grow from our
+        // biggest cave
+        chunk.make_connected((0, 0), Repair::Tunnel);
+
+        chunk
+    }
+
+    /// Run a single step of our cave automaton over the whole chunk
+    pub fn smooth(&mut self, rules: CaveRules) {
+        let mut next = self.clone();
+        for (x, y, tile) in self.cells() {
+            let walls = self.wall_neighbours(x, y);
+            let floor = if tile.is_walkable() {
+                !rules.birth[walls]
+            } else {
+                !rules.survival[walls]
+            };
+            next.set(x, y, Tile::from_floor(floor));
+        }
+
+        *self = next;
+    }
+
+    /// Fill in any floor pockets and knock out any wall islands smaller than `min_size`
+    pub fn remove_pockets(&mut self, min_size: usize) {
+        for floor in [true, false].iter() {
+            for region in self.regions(*floor) {
+                if region.len() < min_size {
+                    for (x, y) in region {
+                        self.set(x, y, Tile::from_floor(!*floor));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Flood fill all the orthogonally connected regions of cells that are walkable if `floor`
+    pub fn regions(&self, floor: bool) -> Vec<Vec<(usize, usize)>> {
+        let mut visited = vec![false; self.width() * self.height()];
+        let mut regions = vec![];
+
+        for (x, y, tile) in self.cells() {
+            if visited[y * self.width() + x] || tile.is_walkable() != floor {
+                continue;
+            }
+
+            let mut region = vec![];
+            let mut open = vec![(x, y)];
+            visited[y * self.width() + x] = true;
+            while let Some((cx, cy)) = open.pop() {
+                region.push((cx, cy));
+
+                for (nx, ny, neighbour) in self.neighbours(cx, cy) {
+                    let index = ny * self.width() + nx;
+                    if !visited[index] && neighbour.is_walkable() == floor {
+                        visited[index] = true;
+                        open.push((nx, ny));
+                    }
+                }
+            }
+
+            regions.push(region);
+        }
+
+        regions
+    }
+
+    /// Count the walls in the 8 cells around us, treating anything outside our chunk as a wall
+    fn wall_neighbours(&self, x: usize, y: usize) -> usize {
+        8 - self
+            .surrounding(x, y)
+            .filter(|(_, _, tile)| tile.is_walkable())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::level::ascii::parse_level;
+
+    use super::*;
+
+    fn caves(seed: u64) -> Chunk {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        Chunk::caves(&mut rng, 40, 30, 0.45, 4, CaveRules::default())
+    }
+
+    #[test]
+    fn rules_ignore_impossible_counts() {
+        let rules = CaveRules::new(&[5, 9, 100], &[4, 42]);
+
+        assert_eq!(rules.birth.iter().filter(|rule| **rule).count(), 1);
+        assert!(rules.birth[5]);
+        assert_eq!(rules.survival.iter().filter(|rule| **rule).count(), 1);
+        assert!(rules.survival[4]);
+    }
+
+    #[test]
+    fn caves_are_closed_in_and_connected() {
+        for seed in 0..8 {
+            let chunk = caves(seed);
+
+            // nothing can walk out of our cave
+            for (x, y, tile) in chunk.cells() {
+                if x == 0 || y == 0 || x == 39 || y == 29 {
+                    assert_eq!(tile, Tile::Wall, "seed {} at ({}, {})", seed, x, y);
+                }
+            }
+
+            // and we can walk everywhere inside it, which is neither solid rock nor wide open
+            let sizes = chunk.connectivity().sizes();
+            assert_eq!(sizes.len(), 1, "seed {}", seed);
+            assert!(sizes[0] > 40 * 30 / 5, "seed {}: {:?}", seed, sizes);
+            assert!(sizes[0] < 40 * 30 * 4 / 5, "seed {}: {:?}", seed, sizes);
+        }
+    }
+
+    #[test]
+    fn smoothing_knocks_out_lone_walls_and_fills_in_corners() {
+        let mut chunk = parse_level("#####\n#...#\n#.#.#\n#...#\n#####")
+            .unwrap()
+            .chunk;
+        chunk.smooth(CaveRules::default());
+
+        // our lone wall has no walls around it, while our corners have 6
+        assert_eq!(chunk.get(2, 2), Some(Tile::Floor));
+        for (x, y) in [(1, 1), (3, 1), (1, 3), (3, 3)].iter() {
+            assert_eq!(chunk.get(*x, *y), Some(Tile::Wall), "({}, {})", x, y);
+        }
+        // and the middle of each side only has 4
+        assert_eq!(chunk.get(2, 1), Some(Tile::Floor));
+    }
+
+    #[test]
+    fn the_same_rng_grows_the_same_cave() {
+        assert_eq!(caves(11), caves(11));
+        assert_ne!(caves(11), caves(12));
+    }
+
+    #[test]
+    fn pockets_and_islands_are_removed() {
+        let mut chunk = parse_level(
+            "\
+########
+#......#
+#..#...#
+#......#
+#####..#
+#..#...#
+########",
+        )
+        .unwrap()
+        .chunk;
+        let mut expected = chunk.clone();
+        // our pocket of 2 floors is filled in, and our island of 1 wall knocked out
+        expected.set(1, 5, Tile::Wall);
+        expected.set(2, 5, Tile::Wall);
+        expected.set(3, 2, Tile::Floor);
+
+        chunk.remove_pockets(3);
+        assert_eq!(chunk, expected);
+        assert!(chunk.connectivity().is_connected());
+    }
+}
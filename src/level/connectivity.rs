@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+
+use crate::level::{Chunk, Tile};
+
+/// The walkable regions of a chunk, every cell belongs to at most one region
+pub struct Connectivity {
+    /// the cells of each region, largest first
+    pub regions: Vec<Vec<(usize, usize)>>,
+    width: usize,
+    labels: Vec<Option<usize>>,
+}
+
+impl Connectivity {
+    /// Which region this cell belongs to, `None` if it isn't walkable
+    pub fn region_at(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width {
+            self.labels.get(y * self.width + x).cloned().flatten()
+        } else {
+            None
+        }
+    }
+
+    pub fn sizes(&self) -> Vec<usize> {
+        self.regions.iter().map(|region| region.len()).collect()
+    }
+
+    /// Can we walk everywhere we can stand
+    pub fn is_connected(&self) -> bool {
+        self.regions.len() <= 1
+    }
+}
+
+/// What to do with the regions we can't reach
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Repair {
+    /// fill them in with walls
+    Remove,
+    /// dig corridors through to them
+    Tunnel,
+}
+
+impl Chunk {
+    /// Flood fill our walkable cells into their orthogonally connected regions
+    pub fn connectivity(&self) -> Connectivity {
+        let mut regions = self.regions(true);
+        regions.sort_by_key(|region| std::cmp::Reverse(region.len()));
+
+        let mut labels = vec![None; self.width() * self.height()];
+        for (index, region) in regions.iter().enumerate() {
+            for (x, y) in region.iter() {
+                labels[y * self.width() + x] = Some(index);
+            }
+        }
+
+        Connectivity {
+            regions,
+            width: self.width(),
+            labels,
+        }
+    }
+
+    /// Make sure every walkable cell can be reached from `from`. If `from` isn't walkable we keep
+    /// our largest region instead.
+    pub fn make_connected(&mut self, from: (usize, usize), repair: Repair) {
+        let connectivity = self.connectivity();
+        let keep = match connectivity.region_at(from.0, from.1) {
+            Some(region) => region,
+            None if !connectivity.regions.is_empty() => 0,
+            None => return,
+        };
+
+        match repair {
+            Repair::Remove => {
+                for (index, region) in connectivity.regions.iter().enumerate() {
+                    if index != keep {
+                        for (x, y) in region.iter() {
+                            self.set(*x, *y, Tile::Wall);
+                        }
+                    }
+                }
+            }
+            Repair::Tunnel => {
+                let mut connected = vec![false; self.width() * self.height()];
+                for (x, y) in connectivity.regions[keep].iter() {
+                    connected[y * self.width() + x] = true;
+                }
+
+                // keep digging towards the closest region we haven't reached yet until there aren't any
+                while let Some(path) = self.closest_unconnected(&connected) {
+                    let (x, y) = *path.last().unwrap();
+                    let region = connectivity.region_at(x, y).unwrap();
+                    for (x, y) in path {
+                        if !matches!(self.get(x, y), Some(tile) if tile.is_walkable()) {
+                            self.set(x, y, Tile::Floor);
+                        }
+                        connected[y * self.width() + x] = true;
+                    }
+                    for (x, y) in connectivity.regions[region].iter() {
+                        connected[y * self.width() + x] = true;
+                    }
+                }
+
+                // anything we couldn't dig to is only reachable through our border
+                self.make_connected(from, Repair::Remove);
+            }
+        }
+    }
+
+    /// Breadth first search out from every connected cell for the nearest walkable cell that isn't,
+    /// returning the cells between them. We never dig through our border so we don't open our
+    /// chunk up onto whatever is next to it.
+    fn closest_unconnected(&self, connected: &[bool]) -> Option<Vec<(usize, usize)>> {
+        let width = self.width();
+        let mut previous = vec![None; width * self.height()];
+        let mut visited = connected.to_vec();
+        let mut open = self
+            .cells()
+            .filter(|(x, y, _)| connected[y * width + x])
+            .map(|(x, y, _)| (x, y))
+            .collect::<VecDeque<_>>();
+
+        while let Some((x, y)) = open.pop_front() {
+            for (nx, ny, tile) in self.neighbours(x, y) {
+                let index = ny * width + nx;
+                if visited[index] {
+                    continue;
+                }
+                visited[index] = true;
+                previous[index] = Some((x, y));
+
+                if tile.is_walkable() {
+                    // walk back until we're home again
+                    let mut path = vec![(nx, ny)];
+                    let mut cell = (x, y);
+                    while !connected[cell.1 * width + cell.0] {
+                        path.push(cell);
+                        cell = previous[cell.1 * width + cell.0].unwrap();
+                    }
+                    path.reverse();
+                    return Some(path);
+                }
+
+                let border = nx == 0 || ny == 0 || nx + 1 == width || ny + 1 == self.height();
+                if !border {
+                    open.push_back((nx, ny));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::ascii::parse_level;
+
+    use super::*;
+
+    /// A big cave on our left, a smaller one on our right and a single floor in the corner
+    fn islands() -> Chunk {
+        parse_level(
+            "\
+#########
+#...#...#
+#...#...#
+#...#####
+#...###.#
+#########",
+        )
+        .unwrap()
+        .chunk
+    }
+
+    #[test]
+    fn regions_are_labelled_largest_first() {
+        let connectivity = islands().connectivity();
+
+        assert_eq!(connectivity.sizes(), vec![12, 6, 1]);
+        assert!(!connectivity.is_connected());
+        assert_eq!(connectivity.region_at(1, 1), Some(0));
+        assert_eq!(connectivity.region_at(5, 2), Some(1));
+        assert_eq!(connectivity.region_at(7, 4), Some(2));
+        assert_eq!(connectivity.region_at(0, 0), None);
+        assert_eq!(connectivity.region_at(9, 1), None);
+    }
+
+    #[test]
+    fn regions_split_on_either_kind_of_cell() {
+        let chunk = islands();
+
+        let mut floors = chunk.regions(true);
+        floors.sort_by_key(|region| region.len());
+        assert_eq!(
+            floors.iter().map(|region| region.len()).collect::<Vec<_>>(),
+            vec![1, 6, 12]
+        );
+        // every wall touches our border, so they're all one region
+        assert_eq!(chunk.regions(false).len(), 1);
+    }
+
+    #[test]
+    fn removing_keeps_only_the_region_we_start_in() {
+        let mut chunk = islands();
+        chunk.make_connected((5, 1), Repair::Remove);
+
+        assert_eq!(chunk.connectivity().sizes(), vec![6]);
+        assert_eq!(chunk.get(1, 1), Some(Tile::Wall));
+        assert_eq!(chunk.get(7, 4), Some(Tile::Wall));
+    }
+
+    #[test]
+    fn removing_from_a_wall_keeps_our_largest_region() {
+        let mut chunk = islands();
+        chunk.make_connected((0, 0), Repair::Remove);
+
+        assert_eq!(chunk.connectivity().sizes(), vec![12]);
+    }
+
+    #[test]
+    fn tunnels_join_every_region_without_touching_our_border() {
+        let original = islands();
+        let mut chunk = original.clone();
+        chunk.make_connected((1, 1), Repair::Tunnel);
+
+        let connectivity = chunk.connectivity();
+        assert!(connectivity.is_connected());
+        // we only ever dig, and only as much as we need to
+        for (x, y, tile) in original.cells() {
+            if tile.is_walkable() {
+                assert_eq!(chunk.get(x, y), Some(tile), "({}, {})", x, y);
+            }
+            let border = x == 0 || y == 0 || x == 8 || y == 5;
+            if border {
+                assert_eq!(chunk.get(x, y), Some(Tile::Wall), "({}, {})", x, y);
+            }
+        }
+        // one cell joins our caves and one more reaches our corner
+        assert_eq!(connectivity.sizes(), vec![12 + 6 + 1 + 2]);
+    }
+}
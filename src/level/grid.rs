@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+
+/// How our tiles are laid out in the world. Tile `(0, 0)` is centered on `origin` and every tile
+/// is `tile_size` across, tile coordinates are signed so they keep going across streamed chunks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelGrid {
+    pub origin: Vec3,
+    pub tile_size: f32,
+}
+
+impl Default for LevelGrid {
+    fn default() -> Self {
+        LevelGrid {
+            origin: Vec3::ZERO,
+            tile_size: 1.,
+        }
+    }
+}
+
+impl LevelGrid {
+    /// The tile containing this world position
+    pub fn world_to_tile(&self, position: Vec3) -> (i32, i32) {
+        let local = (position - self.origin) / self.tile_size;
+        (local.x.round() as i32, local.z.round() as i32)
+    }
+
+    /// The world position of the middle of this tile, on our ground
+    pub fn tile_to_world(&self, (x, z): (i32, i32)) -> Vec3 {
+        self.origin + Vec3::new(x as f32, 0., z as f32) * self.tile_size
+    }
+
+    /// Where a ray from our camera through our cursor hits our ground
+    pub fn cursor_to_ground(
+        &self,
+        window: &Window,
+        camera: &Camera,
+        camera_transform: &GlobalTransform,
+    ) -> Option<Vec3> {
+        let cursor_position = window.cursor_position()?;
+        let gpu_to_world = inv_camera_projection(camera, camera_transform);
+
+        // transform our cursor position into our normalized device coordinates
+        let screen_size = Vec2::new(window.width(), window.height());
+        let normalized_cursor = (cursor_position / screen_size) * 2. - Vec2::splat(1.);
+
+        // borrowed from https://github.com/aevyrie/bevy_mod_raycast/blob/master/src/primitives.rs
+        // deal with near and far to support ortho cameras
+        let cursor_near_gpu = normalized_cursor.extend(-1.);
+        let cursor_far_gpu = normalized_cursor.extend(1.);
+
+        // measure everything from our ground so the math below can treat it as y = 0
+        let ground = Vec3::new(0., self.origin.y, 0.);
+        let cursor_near_world = gpu_to_world.project_point3(cursor_near_gpu) - ground;
+        let cursor_far_world = gpu_to_world.project_point3(cursor_far_gpu) - ground;
+
+        // in world coordinates, a ray from our near to far plane through our cursor
+        let cursor_ray = cursor_far_world - cursor_near_world;
+
+        // get the negative normal of our ground to our near world cursor
+        let ground_near_normal = Vec3::new(0., -cursor_near_world.y, 0.);
+
+        // Using the dot product we have
+        // ground_near_normal · cursor_ray = |cursor_ray| |ground_near_normal| cos(θ)
+        // Using sohCAHtoa we have
+        // cos(θ) = |ground_near_normal| / |ray_to_ground|
+        // since we want |ray_to_ground| we can do
+        // |ray_to_ground| = |ground_near_normal| / ( ground_near_normal · cursor_ray / |cursor_ray| |ground_near_normal|)
+        // which reduces to
+        // |ray_to_ground| = (|ground_near_normal|² * |cursor_ray|) /  (ground_near_normal · cursor_ray)
+        let distance_to_ground = (cursor_near_world.y.powf(2.) * cursor_ray.length())
+            / ground_near_normal.dot(cursor_ray);
+        let ray_to_ground = cursor_ray.normalize() * distance_to_ground;
+
+        Some(ray_to_ground + cursor_near_world + ground)
+    }
+
+    /// The tile our cursor is pointing at
+    pub fn tile_under_cursor(
+        &self,
+        window: &Window,
+        camera: &Camera,
+        camera_transform: &GlobalTransform,
+    ) -> Option<(i32, i32)> {
+        self.cursor_to_ground(window, camera, camera_transform)
+            .map(|ground| self.world_to_tile(ground))
+    }
+}
+
+/// Returns a matrix that can transform GPU to World coordinates
+fn inv_camera_projection(camera: &Camera, transform: &GlobalTransform) -> Mat4 {
+    let camera_position = transform.compute_matrix();
+    let projection: Mat4 = camera.projection_matrix;
+
+    camera_position * projection.inverse()
+}
@@ -0,0 +1,406 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::pipeline::PrimitiveTopology;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::math::Point;
+
+use crate::level::{Chunk, Tile};
+
+/// How our chunks draw their walls
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WallStyle {
+    /// a straight wall between every blocking and open cell
+    Blocks,
+    /// a smooth outline around our blocking cells, with diagonals instead of steps
+    Smooth,
+}
+
+impl Default for WallStyle {
+    fn default() -> Self {
+        WallStyle::Blocks
+    }
+}
+
+/// The geometry of our smooth walls, in the space of our chunk with cell `(0, 0)` at the origin
+pub struct WallOutline {
+    pub mesh: Mesh,
+    /// matches our mesh, `None` if we didn't have any walls
+    pub collider: Option<ColliderShape>,
+}
+
+impl Chunk {
+    /// March squares between the centers of our cells, outlining our blocking cells with walls that
+    /// stand on the lowest floor they touch and rise `wall_height` above the highest one, capped
+    /// off on top.
+    ///
+    /// `outside` looks up the cells around our chunk and anything it doesn't know about blocks, so
+    /// a chunk on its own is walled in the same on every side. A square reaching over one of our
+    /// seams belongs to whichever chunk its north west corner is in, so we only march the squares
+    /// past our west and north seams when `outside` doesn't know that corner and nobody else will.
+    pub fn wall_outline<F: Fn(isize, isize) -> Option<Tile>>(
+        &self,
+        tile_size: f32,
+        wall_height: f32,
+        outside: &F,
+    ) -> WallOutline {
+        let inside = |x: isize, z: isize| {
+            if x < 0 || z < 0 || x as usize >= self.width() || z as usize >= self.height() {
+                None
+            } else {
+                Some((x as usize, z as usize))
+            }
+        };
+        let solid = |x: isize, z: isize| {
+            inside(x, z)
+                .and_then(|(x, z)| self.get(x, z))
+                .or_else(|| outside(x, z))
+                .map_or(true, |tile| tile.blocks())
+        };
+
+        let mut builder = OutlineBuilder {
+            positions: vec![],
+            normals: vec![],
+            indices: vec![],
+        };
+
+        for z in -1..self.height() as isize {
+            for x in -1..self.width() as isize {
+                if (x < 0 || z < 0) && outside(x, z).is_some() {
+                    continue;
+                }
+
+                // the corners of our square, going around clockwise from the top left
+                let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+
+                // we only know the elevations of our own cells, and every square has at least one
+                let (bottom, top) = corners
+                    .iter()
+                    .filter_map(|(cx, cz)| inside(*cx, *cz))
+                    .filter_map(|(cx, cz)| self.elevation(cx, cz))
+                    .fold((f32::MAX, f32::MIN), |(bottom, top), elevation| {
+                        (bottom.min(elevation), top.max(elevation))
+                    });
+                let top = top + wall_height;
+
+                // walk around our square collecting the outline of its solid part, every edge
+                // between differing corners gets split at its middle
+                let mut outline = vec![];
+                for (index, (cx, cz)) in corners.iter().enumerate() {
+                    let (nx, nz) = corners[(index + 1) % 4];
+                    let here = solid(*cx, *cz);
+                    if here {
+                        outline.push((Vec2::new(*cx as f32, *cz as f32), false));
+                    }
+                    if here != solid(nx, nz) {
+                        let middle = Vec2::new(*cx as f32 + nx as f32, *cz as f32 + nz as f32) / 2.;
+                        outline.push((middle, true));
+                    }
+                }
+                if outline.len() < 3 {
+                    continue;
+                }
+
+                let points = outline.iter().map(|(point, _)| *point).collect::<Vec<_>>();
+                builder.add_cap(&points, top);
+
+                // an edge between two middles cuts through our square, that's our wall
+                let center =
+                    points.iter().fold(Vec2::ZERO, |sum, point| sum + *point) / points.len() as f32;
+                for index in 0..outline.len() {
+                    let (start, start_middle) = outline[index];
+                    let (end, end_middle) = outline[(index + 1) % outline.len()];
+                    if start_middle && end_middle {
+                        builder.add_wall(start, end, center, bottom, top);
+                    }
+                }
+            }
+        }
+
+        builder.build(tile_size)
+    }
+}
+
+struct OutlineBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl OutlineBuilder {
+    /// Fan out the top of a convex slice of wall at height `top`
+    fn add_cap(&mut self, points: &[Vec2], top: f32) {
+        let base = self.positions.len() as u32;
+        for point in points {
+            self.positions.push([point.x, top, point.y]);
+            self.normals.push([0., 1., 0.]);
+        }
+
+        // our points go clockwise looking down from above, so we flip every triangle of our fan
+        // round to counter clockwise to face it up
+        for index in 1..points.len() as u32 - 1 {
+            self.indices
+                .extend_from_slice(&[base, base + index + 1, base + index]);
+        }
+    }
+
+    /// A vertical quad from `start` to `end` between heights `bottom` and `top`, facing away from
+    /// the solid `inside` of its square
+    fn add_wall(&mut self, start: Vec2, end: Vec2, inside: Vec2, bottom: f32, top: f32) {
+        let along = end - start;
+        let mut normal = Vec2::new(along.y, -along.x).normalize();
+        if normal.dot(inside - start) > 0. {
+            normal = -normal;
+        }
+
+        // wind our quad so its front faces along our normal
+        let (start, end) = if along.perp_dot(normal) < 0. {
+            (end, start)
+        } else {
+            (start, end)
+        };
+
+        let base = self.positions.len() as u32;
+        for (point, y) in [(start, bottom), (end, bottom), (end, top), (start, top)].iter() {
+            self.positions.push([point.x, *y, point.y]);
+            self.normals.push([normal.x, 0., normal.y]);
+        }
+        self.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    fn build(self, tile_size: f32) -> WallOutline {
+        let positions = self
+            .positions
+            .iter()
+            .map(|[x, y, z]| [x * tile_size, y * tile_size, z * tile_size])
+            .collect::<Vec<_>>();
+
+        let collider = if self.indices.is_empty() {
+            None
+        } else {
+            Some(ColliderShape::trimesh(
+                positions
+                    .iter()
+                    .map(|p| Into::<Point<_>>::into(*p))
+                    .collect(),
+                self.indices.chunks(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            ))
+        };
+
+        let uvs = positions
+            .iter()
+            .map(|[x, _, z]| [*x, *z])
+            .collect::<Vec<_>>();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+
+        WallOutline { mesh, collider }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::mesh::VertexAttributeValues;
+    use bevy_rapier3d::rapier::math::{Point, Vector};
+    use bevy_rapier3d::rapier::parry::query::{Ray, RayCast};
+
+    use crate::level::ascii::parse_level;
+
+    use super::*;
+
+    fn positions(mesh: &Mesh) -> Vec<Vec3> {
+        match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float3(values)) => {
+                values.iter().map(|p| Vec3::from(*p)).collect()
+            }
+            _ => panic!("our outline doesn't have any positions"),
+        }
+    }
+
+    fn normals(mesh: &Mesh) -> Vec<Vec3> {
+        match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float3(values)) => {
+                values.iter().map(|n| Vec3::from(*n)).collect()
+            }
+            _ => panic!("our outline doesn't have any normals"),
+        }
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[usize; 3]> {
+        match mesh.indices() {
+            Some(Indices::U32(indices)) => indices
+                .chunks(3)
+                .map(|c| [c[0] as usize, c[1] as usize, c[2] as usize])
+                .collect(),
+            _ => panic!("our outline doesn't have any indices"),
+        }
+    }
+
+    /// The normals of every wall we built, one per quad
+    fn wall_normals(mesh: &Mesh) -> Vec<Vec3> {
+        let normals = normals(mesh);
+        triangles(mesh)
+            .iter()
+            .map(|[a, _, _]| normals[*a])
+            .filter(|normal| normal.y == 0.)
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|quad| quad[0])
+            .collect()
+    }
+
+    fn single_floor() -> Chunk {
+        parse_level(".").unwrap().chunk
+    }
+
+    #[test]
+    fn one_floor_is_walled_in_on_every_side() {
+        let outline = single_floor().wall_outline(1., 1., &|_, _| None);
+
+        // each of the 4 squares around our floor has 3 solid corners, capped with a fan of 3
+        // triangles and cut off by a diagonal wall of 2
+        assert_eq!(triangles(&outline.mesh).len(), 4 * (3 + 2));
+
+        let walls = wall_normals(&outline.mesh);
+        assert_eq!(walls.len(), 4);
+        for (dx, dz) in [(1., 1.), (-1., 1.), (1., -1.), (-1., -1.)].iter() {
+            let facing = Vec3::new(*dx, 0., *dz).normalize();
+            assert_eq!(
+                walls.iter().filter(|n| n.dot(facing) > 0.99).count(),
+                1,
+                "one wall faces {:?}",
+                facing
+            );
+        }
+    }
+
+    #[test]
+    fn cells_outside_our_chunk_block_on_every_side() {
+        let chunk = Chunk::new(4, 3, Tile::Floor);
+        let walls = wall_normals(&chunk.wall_outline(1., 1., &|_, _| None).mesh);
+
+        // every side gets a straight wall between each pair of its cells, facing into our chunk
+        for (facing, count) in [(Vec3::X, 2), (-Vec3::X, 2), (Vec3::Z, 3), (-Vec3::Z, 3)].iter() {
+            assert_eq!(
+                walls.iter().filter(|n| n.dot(*facing) > 0.99).count(),
+                *count,
+                "walls facing {:?}",
+                facing
+            );
+        }
+
+        let positions = positions(&chunk.wall_outline(1., 1., &|_, _| None).mesh);
+        let min = positions
+            .iter()
+            .fold(Vec3::splat(f32::MAX), |a, p| a.min(*p));
+        let max = positions
+            .iter()
+            .fold(Vec3::splat(f32::MIN), |a, p| a.max(*p));
+        assert_eq!(min, Vec3::new(-1., 0., -1.));
+        assert_eq!(max, Vec3::new(4., 1., 3.));
+    }
+
+    #[test]
+    fn squares_over_our_west_and_north_seams_belong_to_our_neighbours() {
+        let chunk = Chunk::new(4, 3, Tile::Floor);
+        let outline = chunk.wall_outline(1., 1., &|x, z| {
+            if x < 0 || z < 0 {
+                Some(Tile::Wall)
+            } else {
+                None
+            }
+        });
+
+        // our neighbours draw the walls facing us from the west and north
+        let walls = wall_normals(&outline.mesh);
+        assert!(!walls.iter().any(|n| n.x > 0.01 || n.z > 0.01));
+        let min = positions(&outline.mesh)
+            .iter()
+            .fold(Vec3::splat(f32::MAX), |a, p| a.min(*p));
+        assert_eq!(min, Vec3::ZERO);
+    }
+
+    #[test]
+    fn our_triangles_face_their_normals() {
+        let chunk = parse_level("#####\n#...#\n#.#.#\n#..##\n#####")
+            .unwrap()
+            .chunk;
+        let outline = chunk.wall_outline(2., 1., &|_, _| None);
+        let (positions, normals) = (positions(&outline.mesh), normals(&outline.mesh));
+
+        for [a, b, c] in triangles(&outline.mesh) {
+            let facing = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+            assert!(
+                facing.length() > 0.,
+                "triangle {:?} is degenerate",
+                [a, b, c]
+            );
+            assert!(
+                facing.normalize().dot(normals[a]) > 0.99,
+                "triangle {:?} faces {:?} not {:?}",
+                [a, b, c],
+                facing,
+                normals[a]
+            );
+        }
+
+        // and our caps all face straight up
+        assert!(normals
+            .iter()
+            .all(|normal| *normal == Vec3::Y || normal.y == 0.));
+    }
+
+    #[test]
+    fn walls_stand_on_our_floors() {
+        let mut chunk = Chunk::new(2, 1, Tile::Floor);
+        chunk.set_elevation(0, 0, 2.);
+        chunk.set_elevation(1, 0, -1.);
+        let outline = chunk.wall_outline(1., 0.5, &|_, _| None);
+        let positions = positions(&outline.mesh);
+        let normals = normals(&outline.mesh);
+
+        // our walls reach from our lowest floor to half a tile over our highest
+        let heights = positions
+            .iter()
+            .zip(normals.iter())
+            .filter(|(_, normal)| normal.y == 0.)
+            .map(|(position, _)| position.y);
+        assert_eq!(heights.clone().fold(f32::MAX, f32::min), -1.);
+        assert_eq!(heights.fold(f32::MIN, f32::max), 2.5);
+
+        // and the walls around our west floor don't dip below it
+        assert!(positions
+            .iter()
+            .zip(normals.iter())
+            .filter(|(position, normal)| normal.y == 0. && position.x < 0.)
+            .all(|(position, _)| position.y >= 2.));
+    }
+
+    #[test]
+    fn our_collider_matches_our_mesh() {
+        let outline = single_floor().wall_outline(2., 1., &|_, _| None);
+        let collider = outline.collider.expect("we have walls");
+        let trimesh = collider.as_trimesh().expect("our collider is a trimesh");
+
+        assert_eq!(trimesh.vertices().len(), positions(&outline.mesh).len());
+        assert_eq!(trimesh.indices().len(), triangles(&outline.mesh).len());
+
+        // dropping onto our walls lands on their caps, and we fall straight through our floor
+        let drop = |x: f32, z: f32| {
+            collider.cast_local_ray(&Ray::new(Point::new(x, 10., z), -Vector::y()), 100., true)
+        };
+        assert!(matches!(drop(-1.8, -1.8), Some(distance) if (distance - 8.).abs() < 1e-4));
+        assert_eq!(drop(0., 0.), None);
+    }
+
+    #[test]
+    fn open_chunks_have_no_collider() {
+        let outline = Chunk::new(3, 3, Tile::Floor).wall_outline(1., 1., &|_, _| Some(Tile::Floor));
+
+        assert!(outline.collider.is_none());
+        assert!(triangles(&outline.mesh).is_empty());
+    }
+}
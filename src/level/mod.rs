@@ -0,0 +1,306 @@
+mod ascii;
+mod asset;
+mod bsp;
+mod builder;
+mod caves;
+mod connectivity;
+mod grid;
+mod marching;
+mod pathfinding;
+mod placed;
+mod png;
+mod save;
+mod streaming;
+mod tile;
+mod tiled;
+mod wfc;
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::level::ascii::AsciiLevelLoader;
+use crate::level::asset::level_spawn_system;
+use crate::level::png::PngLevelLoader;
+use crate::level::save::SavedLevelLoader;
+use crate::level::streaming::ChunkStreamingPlugin;
+use crate::level::tiled::TiledLevelLoader;
+
+pub use asset::{CurrentLevel, Level, LevelSource, PlacedEntity};
+pub use bsp::{BspConfig, Dungeon, Room};
+pub use builder::ChunkEntities;
+pub use caves::CaveRules;
+pub use connectivity::{Connectivity, Repair};
+pub use grid::LevelGrid;
+pub use marching::{WallOutline, WallStyle};
+pub use pathfinding::{find_path, waypoints, PathCache, PathOptions};
+pub use placed::{PlacedContext, PlacedEntitySpawner, PlacedEntitySpawners};
+pub use png::ColorLegend;
+pub use save::{save_level, LevelFormat};
+pub use streaming::ChunkManager;
+pub use tile::{Tile, TileCollider, TileSensor};
+pub use tiled::{TiledTable, TiledTile};
+pub use wfc::{Direction, TileId, TileRule, Tileset};
+
+/// Loads or generates our level and spawns it.
+///
+/// We only fill in our resources with their defaults when they're missing, so insert your own
+/// `LevelSource`, `WallStyle`, `ColorLegend`, `TiledTable` or `PlacedEntitySpawners` before adding
+/// us to change them.
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<LevelSource>()
+            .init_resource::<LevelGrid>()
+            .init_resource::<WallStyle>()
+            .init_resource::<PathCache>()
+            .init_resource::<PlacedEntitySpawners>()
+            .add_asset::<Level>()
+            .init_asset_loader::<AsciiLevelLoader>()
+            .init_asset_loader::<SavedLevelLoader>()
+            .init_asset_loader::<TiledLevelLoader>()
+            .init_asset_loader::<PngLevelLoader>()
+            .add_plugin(ChunkStreamingPlugin)
+            .add_system(level_spawn_system.system());
+    }
+}
+
+/// The orthogonal offsets around a cell
+const NEIGHBOURS: [(isize, isize); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+/// Every offset around a cell, including the diagonals
+const SURROUNDING: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+];
+
+/// A `width` x `height` grid of tiles, stored row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    width: usize,
+    height: usize,
+    tiles: Vec<Tile>,
+    /// how far each tile's floor is raised above our ground, in tiles
+    elevations: Vec<f32>,
+    /// floors drawn with a different mesh from the one their tile uses
+    meshes: HashMap<(usize, usize), String>,
+    /// floors that slope up towards the cell next to them
+    ramps: HashMap<(usize, usize), Direction>,
+}
+
+impl Chunk {
+    pub fn new(width: usize, height: usize, fill: Tile) -> Chunk {
+        Chunk {
+            width,
+            height,
+            tiles: vec![fill; width * height],
+            elevations: vec![0.; width * height],
+            meshes: HashMap::new(),
+            ramps: HashMap::new(),
+        }
+    }
+
+    pub fn random<R: Rng>(rng: &mut R, width: usize, height: usize) -> Chunk {
+        let mut chunk = Chunk::new(width, height, Tile::Wall);
+        for tile in chunk.tiles.iter_mut() {
+            *tile = Tile::from_floor(rng.gen_bool(0.5));
+        }
+
+        chunk
+    }
+
+    pub fn arena(width: usize, height: usize) -> Chunk {
+        let mut chunk = Chunk::new(width, height, Tile::Floor);
+        chunk.fill_border(Tile::Wall);
+
+        chunk
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<Tile> {
+        self.index(x, y).map(|index| self.tiles[index])
+    }
+
+    /// Replace the tile at `x`, `y` returning what used to be there, or `None` if we're out of bounds.
+    /// Our new tile brings its own floor mesh.
+    pub fn set(&mut self, x: usize, y: usize, tile: Tile) -> Option<Tile> {
+        let index = self.index(x, y)?;
+        self.meshes.remove(&(x, y));
+
+        Some(std::mem::replace(&mut self.tiles[index], tile))
+    }
+
+    /// How far the floor at `x`, `y` is raised above our ground, in tiles
+    pub fn elevation(&self, x: usize, y: usize) -> Option<f32> {
+        self.index(x, y).map(|index| self.elevations[index])
+    }
+
+    /// Raise (or sink) the floor at `x`, `y`, returning its old elevation
+    pub fn set_elevation(&mut self, x: usize, y: usize, elevation: f32) -> Option<f32> {
+        let index = self.index(x, y)?;
+
+        Some(std::mem::replace(&mut self.elevations[index], elevation))
+    }
+
+    /// Which way the floor at `x`, `y` climbs, if it's a ramp
+    pub fn ramp(&self, x: usize, y: usize) -> Option<Direction> {
+        self.ramps.get(&(x, y)).copied()
+    }
+
+    /// Slope the floor at `x`, `y` from its own elevation on one side up (or down) to the floor of
+    /// the cell it faces in `direction`, `None` flattens it again
+    pub fn set_ramp(&mut self, x: usize, y: usize, direction: Option<Direction>) {
+        if self.index(x, y).is_none() {
+            return;
+        }
+        match direction {
+            Some(direction) => self.ramps.insert((x, y), direction),
+            None => self.ramps.remove(&(x, y)),
+        };
+    }
+
+    /// How high our floor at `x`, `y` is at `(u, v)` across it, from `(0, 0)` at its north west
+    /// corner to `(1, 1)` at its south east, in tiles
+    pub fn floor_height(&self, x: usize, y: usize, (u, v): (f32, f32)) -> Option<f32> {
+        let elevation = self.elevation(x, y)?;
+        let direction = match self.ramp(x, y) {
+            Some(direction) => direction,
+            None => return Some(elevation),
+        };
+
+        // a ramp off the edge of our chunk has nothing to climb to
+        let (dx, dy) = direction.offset();
+        let top = self
+            .elevation((x as isize + dx) as usize, (y as isize + dy) as usize)
+            .unwrap_or(elevation);
+        let along = match direction {
+            Direction::North => 1. - v,
+            Direction::East => u,
+            Direction::South => v,
+            Direction::West => 1. - u,
+        };
+
+        Some(elevation + (top - elevation) * along)
+    }
+
+    /// The name of the mesh in `models.gltf` we lay down at `x`, `y`
+    pub fn floor_mesh(&self, x: usize, y: usize) -> Option<&str> {
+        match self.meshes.get(&(x, y)) {
+            Some(mesh) => Some(mesh.as_str()),
+            None => self.get(x, y)?.floor_mesh(),
+        }
+    }
+
+    /// The mesh we gave the floor at `x`, `y` in place of its tile's own, if we gave it one
+    pub fn floor_mesh_override(&self, x: usize, y: usize) -> Option<&str> {
+        self.meshes.get(&(x, y)).map(|mesh| mesh.as_str())
+    }
+
+    /// Draw the floor at `x`, `y` with a different mesh from its tile's, `None` goes back to our
+    /// tile's mesh
+    pub fn set_floor_mesh(&mut self, x: usize, y: usize, mesh: Option<String>) {
+        if self.index(x, y).is_none() {
+            return;
+        }
+        match mesh {
+            Some(mesh) => self.meshes.insert((x, y), mesh),
+            None => self.meshes.remove(&(x, y)),
+        };
+    }
+
+    /// Every floor we've given a mesh of its own as `((x, y), mesh)`
+    pub fn floor_meshes(&self) -> impl Iterator<Item = ((usize, usize), &str)> + '_ {
+        self.meshes
+            .iter()
+            .map(|(cell, mesh)| (*cell, mesh.as_str()))
+    }
+
+    /// Every cell in our chunk as `(x, y, tile)`, row by row
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, Tile)> + '_ {
+        let width = self.width;
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(move |(index, tile)| (index % width, index / width, *tile))
+    }
+
+    /// The orthogonal cells around `x`, `y` that are inside our chunk
+    pub fn neighbours(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = (usize, usize, Tile)> + '_ {
+        self.offset_cells(x, y, &NEIGHBOURS)
+    }
+
+    /// All 8 cells around `x`, `y` that are inside our chunk
+    pub fn surrounding(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = (usize, usize, Tile)> + '_ {
+        self.offset_cells(x, y, &SURROUNDING)
+    }
+
+    /// The cells whose tile, elevation, ramp or mesh differ between us and a chunk of the same size
+    pub fn changed_cells<'a>(
+        &'a self,
+        other: &'a Chunk,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.cells()
+            .filter(move |(x, y, tile)| {
+                other.get(*x, *y) != Some(*tile)
+                    || other.elevation(*x, *y) != self.elevation(*x, *y)
+                    || other.ramp(*x, *y) != self.ramp(*x, *y)
+                    || other.floor_mesh(*x, *y) != self.floor_mesh(*x, *y)
+            })
+            .map(|(x, y, _)| (x, y))
+    }
+
+    /// Overwrite the outer ring of our chunk
+    pub fn fill_border(&mut self, tile: Tile) {
+        for x in 0..self.width {
+            self.set(x, 0, tile);
+            self.set(x, self.height.saturating_sub(1), tile);
+        }
+        for y in 0..self.height {
+            self.set(0, y, tile);
+            self.set(self.width.saturating_sub(1), y, tile);
+        }
+    }
+
+    fn offset_cells<'a>(
+        &'a self,
+        x: usize,
+        y: usize,
+        offsets: &'static [(isize, isize)],
+    ) -> impl Iterator<Item = (usize, usize, Tile)> + 'a {
+        offsets.iter().filter_map(move |(dx, dy)| {
+            let nx = (x as isize + dx) as usize;
+            let ny = (y as isize + dy) as usize;
+            self.get(nx, ny).map(|tile| (nx, ny, tile))
+        })
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use bevy::prelude::*;
+
+use crate::level::{Chunk, LevelGrid};
+
+/// The cost of an orthogonal step, diagonals cost `14` so we can stick to integers
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+/// What stepping onto a hazard costs on top of the step itself by default, so we'll go up to 10
+/// tiles out of our way to walk around one
+const HAZARD_COST: u32 = 10 * STRAIGHT_COST;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct PathOptions {
+    /// can we step diagonally between cells
+    pub diagonals: bool,
+    /// can a diagonal step squeeze past a blocked cell, if not both cells beside it need to be walkable
+    pub cut_corners: bool,
+    /// what stepping onto a hazard costs on top of the step itself, `None` keeps us off them
+    /// altogether
+    pub hazard_cost: Option<u32>,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            diagonals: true,
+            cut_corners: false,
+            hazard_cost: Some(HAZARD_COST),
+        }
+    }
+}
+
+/// Find the cheapest path across the walkable cells of `chunk` with A*, including both `start`
+/// and `goal`. `None` if we can't get there.
+pub fn find_path(
+    chunk: &Chunk,
+    start: (usize, usize),
+    goal: (usize, usize),
+    options: PathOptions,
+) -> Option<Vec<(usize, usize)>> {
+    let walkable = |x: usize, y: usize| matches!(chunk.get(x, y), Some(tile) if tile.is_walkable());
+    // we can always walk off a hazard we're standing in, but only onto one if we'll pay for it
+    let hazard = |x: usize, y: usize| matches!(chunk.get(x, y), Some(tile) if tile.is_hazard());
+    let enterable =
+        |x: usize, y: usize| walkable(x, y) && (options.hazard_cost.is_some() || !hazard(x, y));
+    if !walkable(start.0, start.1) || !enterable(goal.0, goal.1) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut previous = HashMap::new();
+    let mut costs = HashMap::new();
+    costs.insert(start, 0);
+    open.push(Node {
+        cell: start,
+        estimate: heuristic(start, goal, options.diagonals),
+    });
+
+    while let Some(Node { cell, .. }) = open.pop() {
+        if cell == goal {
+            let mut path = vec![goal];
+            while let Some(&cell) = previous.get(path.last().unwrap()) {
+                path.push(cell);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cost = costs[&cell];
+        for &(dx, dy) in steps(options.diagonals) {
+            let next = (cell.0 as isize + dx, cell.1 as isize + dy);
+            if next.0 < 0 || next.1 < 0 {
+                continue;
+            }
+            let next = (next.0 as usize, next.1 as usize);
+            if !enterable(next.0, next.1) {
+                continue;
+            }
+
+            let diagonal = dx != 0 && dy != 0;
+            if diagonal {
+                let side_x = walkable(next.0, cell.1);
+                let side_y = walkable(cell.0, next.1);
+                let squeezes = if options.cut_corners {
+                    !side_x && !side_y
+                } else {
+                    !side_x || !side_y
+                };
+                if squeezes {
+                    continue;
+                }
+            }
+
+            let mut step_cost = if diagonal {
+                DIAGONAL_COST
+            } else {
+                STRAIGHT_COST
+            };
+            if hazard(next.0, next.1) {
+                step_cost += options.hazard_cost.unwrap_or_default();
+            }
+            let next_cost = cost + step_cost;
+            if !matches!(costs.get(&next), Some(&known) if known <= next_cost) {
+                costs.insert(next, next_cost);
+                previous.insert(next, cell);
+                open.push(Node {
+                    cell: next,
+                    estimate: next_cost + heuristic(next, goal, options.diagonals),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Turn a path of cells into world positions for a chunk whose `(0, 0)` cell sits on the `offset`
+/// tile of our grid, i.e. `Level::offset` centers our levels so cell `x` is at `x - width / 2`
+pub fn waypoints(grid: &LevelGrid, offset: (i32, i32), path: &[(usize, usize)]) -> Vec<Vec3> {
+    path.iter()
+        .map(|(x, z)| grid.tile_to_world((offset.0 + *x as i32, offset.1 + *z as i32)))
+        .collect()
+}
+
+/// The `(start, goal, options)` we found a path for
+type PathKey = ((usize, usize), (usize, usize), PathOptions);
+
+/// Remembers the paths we've found so far, forgetting them all as soon as the chunk we're asked
+/// about moves on to a new generation
+#[derive(Default)]
+pub struct PathCache {
+    generation: Option<usize>,
+    paths: HashMap<PathKey, Option<Vec<(usize, usize)>>>,
+}
+
+impl PathCache {
+    /// Like `find_path`, `generation` needs to change whenever `chunk` does, i.e. by using
+    /// `CurrentLevel::generation`
+    pub fn find_path(
+        &mut self,
+        chunk: &Chunk,
+        generation: usize,
+        start: (usize, usize),
+        goal: (usize, usize),
+        options: PathOptions,
+    ) -> Option<Vec<(usize, usize)>> {
+        if self.generation != Some(generation) {
+            self.generation = Some(generation);
+            self.paths.clear();
+        }
+
+        self.paths
+            .entry((start, goal, options))
+            .or_insert_with(|| find_path(chunk, start, goal, options))
+            .clone()
+    }
+}
+
+/// Our orthogonal steps followed by our diagonal ones
+const STEPS: [(isize, isize); 8] = [
+    (0, -1),
+    (1, 0),
+    (0, 1),
+    (-1, 0),
+    (1, -1),
+    (1, 1),
+    (-1, 1),
+    (-1, -1),
+];
+
+fn steps(diagonals: bool) -> &'static [(isize, isize)] {
+    if diagonals {
+        &STEPS
+    } else {
+        &STEPS[..4]
+    }
+}
+
+/// Octile distance if we can move diagonally, otherwise manhattan
+fn heuristic(from: (usize, usize), to: (usize, usize), diagonals: bool) -> u32 {
+    let dx = (from.0 as isize - to.0 as isize).unsigned_abs() as u32;
+    let dy = (from.1 as isize - to.1 as isize).unsigned_abs() as u32;
+    if diagonals {
+        STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+    } else {
+        STRAIGHT_COST * (dx + dy)
+    }
+}
+
+/// An entry in our open set, ordered so our heap pops the lowest estimate first
+#[derive(Eq, PartialEq)]
+struct Node {
+    cell: (usize, usize),
+    estimate: u32,
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .cmp(&self.estimate)
+            .then_with(|| self.cell.cmp(&other.cell))
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::level::Tile;
+
+    /// What walking our path costs, checking every step is to a neighbouring cell
+    fn cost(path: &[(usize, usize)]) -> u32 {
+        path.windows(2)
+            .map(|step| {
+                let dx = (step[0].0 as isize - step[1].0 as isize).abs();
+                let dy = (step[0].1 as isize - step[1].1 as isize).abs();
+                match (dx, dy) {
+                    (1, 0) | (0, 1) => STRAIGHT_COST,
+                    (1, 1) => DIAGONAL_COST,
+                    _ => panic!("{:?} isn't a single step", step),
+                }
+            })
+            .sum()
+    }
+
+    /// The cheapest cost to every cell from `start` with a plain Dijkstra over the same moves
+    fn cheapest(
+        chunk: &Chunk,
+        start: (usize, usize),
+        options: PathOptions,
+    ) -> HashMap<(usize, usize), u32> {
+        let mut costs = HashMap::new();
+        let mut open = BinaryHeap::new();
+        costs.insert(start, 0);
+        open.push(Node {
+            cell: start,
+            estimate: 0,
+        });
+        while let Some(Node { cell, estimate }) = open.pop() {
+            if estimate > costs[&cell] {
+                continue;
+            }
+            // every walkable cell around us that find_path agrees we can step to
+            for (x, y, tile) in chunk.surrounding(cell.0, cell.1) {
+                if !tile.is_walkable() {
+                    continue;
+                }
+                let step = match find_path(chunk, cell, (x, y), options) {
+                    Some(path) if path.len() == 2 => cost(&path),
+                    _ => continue,
+                };
+                let next_cost = estimate + step;
+                if !matches!(costs.get(&(x, y)), Some(&known) if known <= next_cost) {
+                    costs.insert((x, y), next_cost);
+                    open.push(Node {
+                        cell: (x, y),
+                        estimate: next_cost,
+                    });
+                }
+            }
+        }
+
+        costs
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        // .#
+        // ..
+        let mut chunk = Chunk::new(2, 2, Tile::Floor);
+        chunk.set(1, 0, Tile::Wall);
+
+        let path = find_path(&chunk, (0, 0), (1, 1), PathOptions::default()).unwrap();
+        assert_eq!(path, vec![(0, 0), (0, 1), (1, 1)]);
+
+        let cut = PathOptions {
+            cut_corners: true,
+            ..Default::default()
+        };
+        let path = find_path(&chunk, (0, 0), (1, 1), cut).unwrap();
+        assert_eq!(path, vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn diagonals_never_squeeze_between_walls() {
+        // .#
+        // #.
+        let mut chunk = Chunk::new(2, 2, Tile::Floor);
+        chunk.set(1, 0, Tile::Wall);
+        chunk.set(0, 1, Tile::Wall);
+
+        for cut_corners in [false, true].iter() {
+            let options = PathOptions {
+                cut_corners: *cut_corners,
+                ..Default::default()
+            };
+            assert_eq!(find_path(&chunk, (0, 0), (1, 1), options), None);
+        }
+    }
+
+    #[test]
+    fn unreachable_goals_have_no_path() {
+        let mut chunk = Chunk::arena(7, 7);
+        for y in 0..7 {
+            chunk.set(3, y, Tile::Wall);
+        }
+
+        assert_eq!(
+            find_path(&chunk, (1, 1), (5, 5), PathOptions::default()),
+            None
+        );
+        // and we can't start or finish inside a wall
+        assert_eq!(
+            find_path(&chunk, (0, 0), (1, 1), PathOptions::default()),
+            None
+        );
+        assert_eq!(
+            find_path(&chunk, (1, 1), (3, 3), PathOptions::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn open_paths_cost_their_octile_distance() {
+        let chunk = Chunk::arena(12, 12);
+        let path = find_path(&chunk, (1, 2), (9, 5), PathOptions::default()).unwrap();
+
+        assert_eq!(path.first(), Some(&(1, 2)));
+        assert_eq!(path.last(), Some(&(9, 5)));
+        assert_eq!(cost(&path), 3 * DIAGONAL_COST + 5 * STRAIGHT_COST);
+    }
+
+    #[test]
+    fn paths_walk_around_hazards() {
+        // .....
+        // .LLL.
+        // .L.L.
+        // .....
+        let mut chunk = Chunk::new(5, 4, Tile::Floor);
+        for (x, y) in [(1, 1), (2, 1), (3, 1), (1, 2), (3, 2)].iter() {
+            chunk.set(*x, *y, Tile::Lava);
+        }
+
+        // around the end of our lava is cheaper than straight through it
+        let path = find_path(&chunk, (2, 0), (2, 2), PathOptions::default()).unwrap();
+        assert!(!path
+            .iter()
+            .any(|(x, y)| chunk.get(*x, *y) == Some(Tile::Lava)));
+        assert_eq!(cost(&path), 2 * STRAIGHT_COST + 3 * DIAGONAL_COST);
+
+        // unless we don't mind it
+        let careless = PathOptions {
+            hazard_cost: Some(0),
+            ..Default::default()
+        };
+        let path = find_path(&chunk, (2, 0), (2, 2), careless).unwrap();
+        assert_eq!(path, vec![(2, 0), (2, 1), (2, 2)]);
+
+        // and we'll cross it if there's no other way
+        for x in 1..4 {
+            chunk.set(x, 3, Tile::Lava);
+        }
+        let path = find_path(&chunk, (2, 0), (2, 2), PathOptions::default()).unwrap();
+        assert_eq!(path, vec![(2, 0), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn hazards_can_be_kept_off_of_entirely() {
+        let mut chunk = Chunk::new(5, 1, Tile::Floor);
+        chunk.set(2, 0, Tile::Lava);
+        let careful = PathOptions {
+            hazard_cost: None,
+            ..Default::default()
+        };
+
+        assert_eq!(find_path(&chunk, (0, 0), (4, 0), careful), None);
+        assert_eq!(find_path(&chunk, (0, 0), (2, 0), careful), None);
+        // but we can always step out of one
+        assert_eq!(
+            find_path(&chunk, (2, 0), (4, 0), careful),
+            Some(vec![(2, 0), (3, 0), (4, 0)])
+        );
+    }
+
+    #[test]
+    fn our_cache_keeps_paths_until_our_generation_changes() {
+        let mut chunk = Chunk::arena(7, 7);
+        let mut cache = PathCache::default();
+        let options = PathOptions::default();
+        let path = cache.find_path(&chunk, 0, (1, 1), (5, 5), options);
+        assert!(path.is_some());
+
+        // we trust our generation rather than looking through our chunk
+        for y in 0..7 {
+            chunk.set(3, y, Tile::Wall);
+        }
+        assert_eq!(cache.find_path(&chunk, 0, (1, 1), (5, 5), options), path);
+        assert_eq!(cache.find_path(&chunk, 1, (1, 1), (5, 5), options), None);
+    }
+
+    #[test]
+    fn paths_are_as_cheap_as_dijkstra() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for _ in 0..20 {
+            let chunk = Chunk::random(&mut rng, 12, 12);
+            let floors = chunk
+                .cells()
+                .filter(|(_, _, tile)| tile.is_walkable())
+                .map(|(x, y, _)| (x, y))
+                .collect::<Vec<_>>();
+            let start = floors[0];
+
+            for options in [
+                PathOptions::default(),
+                PathOptions {
+                    diagonals: false,
+                    ..Default::default()
+                },
+            ]
+            .iter()
+            {
+                let cheapest = cheapest(&chunk, start, *options);
+                for goal in floors.iter() {
+                    let path = find_path(&chunk, start, *goal, *options);
+                    assert_eq!(path.as_deref().map(cost), cheapest.get(goal).cloned());
+                }
+            }
+        }
+    }
+}
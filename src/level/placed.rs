@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use bevy::ecs::system::EntityCommands;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::level::{Level, LevelGrid, PlacedEntity};
+
+/// Turns a `PlacedEntity` of one kind into components on the entity we spawned for it. Our entity
+/// already has our `PlacedEntity`, its kind as its name and a transform on the floor of its tile.
+pub type PlacedEntitySpawner = fn(&mut EntityCommands, &PlacedEntity, &PlacedContext);
+
+pub struct PlacedContext<'a> {
+    pub grid: &'a LevelGrid,
+    /// our `models.gltf`, for anything that needs a mesh
+    pub gltf: &'a Handle<Gltf>,
+    /// the middle of the floor of our tile
+    pub position: Vec3,
+}
+
+/// How we spawn each kind of `PlacedEntity`, anything without a spawner is still spawned so
+/// gameplay can find it by its `PlacedEntity`, it just doesn't get anything else.
+///
+/// We look these up every time we spawn a level, so new spawners can be `add`ed to our resource
+/// at any point and they'll be used from our next level on.
+#[derive(Clone)]
+pub struct PlacedEntitySpawners {
+    pub spawners: HashMap<String, PlacedEntitySpawner>,
+}
+
+impl Default for PlacedEntitySpawners {
+    fn default() -> Self {
+        let mut spawners = PlacedEntitySpawners {
+            spawners: HashMap::new(),
+        };
+        spawners
+            .add("trigger", trigger_spawner)
+            .add("light", light_spawner);
+
+        spawners
+    }
+}
+
+impl PlacedEntitySpawners {
+    pub fn add<S: ToString>(&mut self, kind: S, spawner: PlacedEntitySpawner) -> &mut Self {
+        self.spawners.insert(kind.to_string(), spawner);
+
+        self
+    }
+
+    /// Spawn every entity placed in our `level`
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        grid: &LevelGrid,
+        gltf: &Handle<Gltf>,
+        level: &Level,
+    ) -> Vec<Entity> {
+        let offset = level.offset();
+        level
+            .entities
+            .iter()
+            .map(|placed| {
+                let (x, z) = placed.tile;
+                let elevation = level.chunk.elevation(x, z).unwrap_or_default();
+                let position = grid.tile_to_world((offset.0 + x as i32, offset.1 + z as i32))
+                    + Vec3::Y * elevation * grid.tile_size;
+
+                let mut entity_commands = commands.spawn_bundle((
+                    Transform::from_translation(position),
+                    GlobalTransform::identity(),
+                ));
+                entity_commands
+                    .insert(placed.kind.clone())
+                    .insert(placed.clone());
+
+                match self.spawners.get(&placed.kind) {
+                    Some(spawner) => spawner(
+                        &mut entity_commands,
+                        placed,
+                        &PlacedContext {
+                            grid,
+                            gltf,
+                            position,
+                        },
+                    ),
+                    None => log::warn!(
+                        "We don't know how to spawn a \"{}\", it's only a PlacedEntity at {:?}",
+                        placed.kind,
+                        placed.tile
+                    ),
+                }
+
+                entity_commands.id()
+            })
+            .collect()
+    }
+}
+
+/// A sensor filling the cell above our tile, like the ones our hazards get
+fn trigger_spawner(
+    entity_commands: &mut EntityCommands,
+    _placed: &PlacedEntity,
+    context: &PlacedContext,
+) {
+    let size = context.grid.tile_size;
+    let position: Vector<Real> = (context.position + Vec3::Y * 0.5 * size).into();
+    entity_commands.insert_bundle(ColliderBundle {
+        shape: ColliderShape::cuboid(0.5 * size, 0.5 * size, 0.5 * size),
+        collider_type: ColliderType::Sensor,
+        position: Isometry::from(position).into(),
+        flags: ActiveEvents::INTERSECTION_EVENTS.into(),
+        ..Default::default()
+    });
+}
+
+/// A light hanging a tile above our floor
+fn light_spawner(
+    entity_commands: &mut EntityCommands,
+    _placed: &PlacedEntity,
+    context: &PlacedContext,
+) {
+    entity_commands.insert_bundle(LightBundle {
+        transform: Transform::from_translation(context.position + Vec3::Y * context.grid.tile_size),
+        ..Default::default()
+    });
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use image::ImageFormat;
+
+use crate::level::{Chunk, Level, Tile};
+
+/// Maps the colors of our png levels onto our tiles, anything fully transparent is void.
+///
+/// Our png loader takes its own copy of this when it's created, so changing it afterwards doesn't
+/// change how we read our levels.
+#[derive(Clone, Debug)]
+pub struct ColorLegend {
+    pub tiles: HashMap<[u8; 3], Tile>,
+}
+
+impl Default for ColorLegend {
+    fn default() -> Self {
+        ColorLegend {
+            tiles: vec![
+                ([0, 0, 0], Tile::Wall),
+                ([255, 255, 255], Tile::Floor),
+                ([255, 0, 0], Tile::Spawn),
+                ([0, 0, 255], Tile::Water),
+                ([255, 128, 0], Tile::Lava),
+                ([255, 255, 0], Tile::Door),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+}
+
+/// Loads a png saved as `.levelpng` as a level, one pixel per tile. Our asset server only has one
+/// loader per extension, so we leave plain `.png`s to bevy's textures.
+pub struct PngLevelLoader {
+    legend: ColorLegend,
+}
+
+impl FromWorld for PngLevelLoader {
+    fn from_world(world: &mut World) -> Self {
+        PngLevelLoader {
+            legend: world
+                .get_resource::<ColorLegend>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl AssetLoader for PngLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let level = parse_png(bytes, &self.legend)
+                .with_context(|| format!("Couldn't parse {}", load_context.path().display()))?;
+
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["levelpng"]
+    }
+}
+
+/// Read a png level, our errors report pixels from the top left starting at `(0, 0)`
+pub fn parse_png(bytes: &[u8], legend: &ColorLegend) -> anyhow::Result<Level> {
+    let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)?.to_rgba8();
+
+    let mut chunk = Chunk::new(image.width() as usize, image.height() as usize, Tile::Void);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            continue;
+        }
+
+        let tile = legend.tiles.get(&[r, g, b]).ok_or_else(|| {
+            anyhow!(
+                "pixel ({}, {}): unknown color #{:02x}{:02x}{:02x}",
+                x,
+                y,
+                r,
+                g,
+                b
+            )
+        })?;
+        chunk.set(x as usize, y as usize, *tile);
+    }
+
+    Ok(Level::new(chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::png::PngEncoder;
+    use image::{ColorType, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn encode(image: &RgbaImage) -> Vec<u8> {
+        let mut bytes = vec![];
+        PngEncoder::new(&mut bytes)
+            .encode(image, image.width(), image.height(), ColorType::Rgba8)
+            .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn pixels_become_tiles() {
+        let colors = [
+            [[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 255]],
+            [[0, 0, 255, 255], [255, 128, 0, 255], [255, 255, 0, 255]],
+            [[12, 34, 56, 0], [255, 255, 255, 255], [0, 0, 0, 255]],
+        ];
+        let image = RgbaImage::from_fn(3, 3, |x, y| Rgba(colors[y as usize][x as usize]));
+
+        let level = parse_png(&encode(&image), &ColorLegend::default()).unwrap();
+        let tiles = level
+            .chunk
+            .cells()
+            .map(|(_, _, tile)| tile)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tiles,
+            vec![
+                Tile::Wall,
+                Tile::Floor,
+                Tile::Spawn,
+                Tile::Water,
+                Tile::Lava,
+                Tile::Door,
+                Tile::Void,
+                Tile::Floor,
+                Tile::Wall,
+            ]
+        );
+        assert_eq!(level.spawn(), Some((2, 0)));
+    }
+
+    #[test]
+    fn unknown_colors_are_rejected() {
+        let mut image = RgbaImage::from_pixel(3, 3, Rgba([255, 255, 255, 255]));
+        image.put_pixel(1, 2, Rgba([1, 2, 3, 255]));
+
+        let error = parse_png(&encode(&image), &ColorLegend::default()).unwrap_err();
+        assert!(error.to_string().contains("pixel (1, 2)"), "{}", error);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use serde::{Deserialize, Serialize};
+
+use crate::level::ascii::{DEFAULT_LEGEND, RAMPS};
+use crate::level::{Chunk, Level, PlacedEntity, Tile};
+
+/// The formats we can save our levels in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LevelFormat {
+    Ron,
+    Json,
+}
+
+impl LevelFormat {
+    /// Pick our format from a path's extension, i.e. `levels/arena.ron`
+    pub fn from_path(path: &Path) -> anyhow::Result<LevelFormat> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Ok(LevelFormat::Ron),
+            Some("json") => Ok(LevelFormat::Json),
+            _ => bail!("{} isn't a .ron or .json file", path.display()),
+        }
+    }
+}
+
+/// How our levels look on disk. Our tiles are written with the same characters as our `.level`
+/// files so they can still be read (and edited) by hand.
+#[derive(Debug, Serialize, Deserialize)]
+struct LevelFile {
+    width: usize,
+    height: usize,
+    /// one string per row
+    tiles: Vec<String>,
+    /// one list of elevations per row, left out when our level is flat
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    elevations: Vec<Vec<f32>>,
+    /// one string per row with the same characters as our `.level` ramps, left out when we don't
+    /// have any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ramps: Vec<String>,
+    /// floors drawn with a mesh other than their tile's
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<FloorMesh>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    entities: Vec<PlacedEntity>,
+    /// the gltf our markers come from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markers: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FloorMesh {
+    tile: (usize, usize),
+    mesh: String,
+}
+
+impl From<&Level> for LevelFile {
+    fn from(level: &Level) -> Self {
+        let chunk = &level.chunk;
+        let legend = DEFAULT_LEGEND
+            .iter()
+            .map(|(c, tile)| (*tile, *c))
+            .collect::<HashMap<_, _>>();
+
+        let rows = (0..chunk.height()).map(|y| (0..chunk.width()).map(move |x| (x, y)));
+        let tiles = rows
+            .clone()
+            .map(|row| {
+                row.map(|(x, y)| legend[&chunk.get(x, y).unwrap()])
+                    .collect()
+            })
+            .collect();
+
+        let flat = chunk
+            .cells()
+            .all(|(x, y, _)| chunk.elevation(x, y) == Some(0.));
+        let elevations = if flat {
+            vec![]
+        } else {
+            rows.clone()
+                .map(|row| row.map(|(x, y)| chunk.elevation(x, y).unwrap()).collect())
+                .collect()
+        };
+
+        let ramp_chars = RAMPS
+            .iter()
+            .map(|(c, direction)| (*direction, *c))
+            .collect::<HashMap<_, _>>();
+        let ramps = if chunk.cells().all(|(x, y, _)| chunk.ramp(x, y).is_none()) {
+            vec![]
+        } else {
+            rows.clone()
+                .map(|row| {
+                    row.map(|(x, y)| chunk.ramp(x, y).map_or('.', |ramp| ramp_chars[&ramp]))
+                        .collect()
+                })
+                .collect()
+        };
+
+        // our chunk doesn't keep its meshes in any order, so sort them to keep our files stable
+        let mut meshes = chunk
+            .floor_meshes()
+            .map(|(tile, mesh)| FloorMesh {
+                tile,
+                mesh: mesh.to_string(),
+            })
+            .collect::<Vec<_>>();
+        meshes.sort_unstable_by_key(|mesh| (mesh.tile.1, mesh.tile.0));
+
+        LevelFile {
+            width: chunk.width(),
+            height: chunk.height(),
+            tiles,
+            elevations,
+            ramps,
+            meshes,
+            entities: level.entities.clone(),
+            markers: level.markers.clone(),
+        }
+    }
+}
+
+impl LevelFile {
+    fn into_level(self) -> anyhow::Result<Level> {
+        let legend = DEFAULT_LEGEND.iter().cloned().collect::<HashMap<_, _>>();
+
+        if self.tiles.len() != self.height {
+            bail!(
+                "expected {} rows of tiles, found {}",
+                self.height,
+                self.tiles.len()
+            );
+        }
+        let mut chunk = Chunk::new(self.width, self.height, Tile::Void);
+        for (y, row) in self.tiles.iter().enumerate() {
+            if row.chars().count() != self.width {
+                bail!("row {}: expected {} tiles", y + 1, self.width);
+            }
+            for (x, c) in row.chars().enumerate() {
+                let tile = legend.get(&c).ok_or_else(|| {
+                    anyhow!("row {}, column {}: unknown tile '{}'", y + 1, x + 1, c)
+                })?;
+                chunk.set(x, y, *tile);
+            }
+        }
+
+        if !self.elevations.is_empty() {
+            if self.elevations.len() != self.height
+                || self.elevations.iter().any(|row| row.len() != self.width)
+            {
+                bail!("our elevations need to be {} x {}", self.width, self.height);
+            }
+            for (y, row) in self.elevations.iter().enumerate() {
+                for (x, elevation) in row.iter().enumerate() {
+                    chunk.set_elevation(x, y, *elevation);
+                }
+            }
+        }
+
+        if !self.ramps.is_empty() {
+            if self.ramps.len() != self.height {
+                bail!("our ramps need to be {} x {}", self.width, self.height);
+            }
+            for (y, row) in self.ramps.iter().enumerate() {
+                if row.chars().count() != self.width {
+                    bail!("our ramps need to be {} x {}", self.width, self.height);
+                }
+                for (x, c) in row.chars().enumerate() {
+                    let ramp = RAMPS.iter().find(|(ramp, _)| *ramp == c);
+                    chunk.set_ramp(x, y, ramp.map(|(_, direction)| *direction));
+                }
+            }
+        }
+
+        for FloorMesh { tile: (x, y), mesh } in self.meshes {
+            if chunk.get(x, y).is_none() {
+                bail!("{} at ({}, {}) is outside of our map", mesh, x, y);
+            }
+            chunk.set_floor_mesh(x, y, Some(mesh));
+        }
+
+        for entity in self.entities.iter() {
+            let (x, y) = entity.tile;
+            if chunk.get(x, y).is_none() {
+                bail!("{} at ({}, {}) is outside of our map", entity.kind, x, y);
+            }
+        }
+
+        Ok(Level {
+            chunk,
+            entities: self.entities,
+            markers: self.markers,
+        })
+    }
+}
+
+impl Level {
+    pub fn to_text(&self, format: LevelFormat) -> anyhow::Result<String> {
+        let file = LevelFile::from(self);
+        Ok(match format {
+            LevelFormat::Ron => ron::ser::to_string_pretty(&file, Default::default())?,
+            LevelFormat::Json => serde_json::to_string_pretty(&file)?,
+        })
+    }
+
+    pub fn from_text(text: &str, format: LevelFormat) -> anyhow::Result<Level> {
+        let file: LevelFile = match format {
+            LevelFormat::Ron => ron::from_str(text)?,
+            LevelFormat::Json => serde_json::from_str(text)?,
+        };
+
+        file.into_level()
+    }
+}
+
+/// Write our level to `path`, picking RON or JSON from its extension. Our asset folder is only
+/// writable on native builds, on the web this always fails.
+pub fn save_level<P: AsRef<Path>>(level: &Level, path: P) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let text = level.to_text(LevelFormat::from_path(path)?)?;
+    std::fs::write(path, text).with_context(|| format!("Couldn't save {}", path.display()))
+}
+
+/// Loads the `.ron` and `.json` levels we've saved
+#[derive(Default)]
+pub struct SavedLevelLoader;
+
+impl AssetLoader for SavedLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let path = load_context.path();
+            let text = std::str::from_utf8(bytes)?;
+            let level = Level::from_text(text, LevelFormat::from_path(path)?)
+                .with_context(|| format!("Couldn't parse {}", path.display()))?;
+
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron", "json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::Direction;
+
+    use super::*;
+
+    #[test]
+    fn levels_round_trip() {
+        let mut chunk = Chunk::arena(6, 5);
+        chunk.set(2, 2, Tile::Spawn);
+        chunk.set(3, 1, Tile::Water);
+        chunk.set(4, 3, Tile::Door);
+        chunk.set_elevation(1, 1, 1.);
+        chunk.set_elevation(2, 1, 0.5);
+        chunk.set_elevation(4, 2, -0.25);
+        chunk.set_ramp(3, 2, Some(Direction::East));
+        chunk.set_ramp(1, 2, Some(Direction::North));
+        chunk.set_floor_mesh(1, 3, Some("lava".to_string()));
+        let level = Level {
+            chunk,
+            entities: vec![
+                PlacedEntity {
+                    kind: "trigger".to_string(),
+                    tile: (3, 3),
+                },
+                PlacedEntity {
+                    kind: "light".to_string(),
+                    tile: (1, 1),
+                },
+            ],
+            markers: Some("models.gltf".to_string()),
+        };
+
+        for format in [LevelFormat::Ron, LevelFormat::Json].iter() {
+            let text = level.to_text(*format).unwrap();
+            assert_eq!(
+                Level::from_text(&text, *format).unwrap(),
+                level,
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn flat_levels_leave_out_their_elevations() {
+        let level = Level::new(Chunk::arena(3, 3));
+        let text = level.to_text(LevelFormat::Json).unwrap();
+
+        assert!(!text.contains("elevations"));
+        assert!(!text.contains("ramps"));
+        assert_eq!(Level::from_text(&text, LevelFormat::Json).unwrap(), level);
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::level::asset::run_streamed;
+use crate::level::builder::spawn_chunk;
+use crate::level::{CaveRules, Chunk, LevelGrid, Repair, Tile, WallStyle};
+use crate::player::Player;
+use crate::seed::WorldSeed;
+
+pub struct ChunkStreamingPlugin;
+
+impl Plugin for ChunkStreamingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ChunkManager>().add_system_set(
+            SystemSet::new()
+                .with_run_criteria(run_streamed.system())
+                .with_system(chunk_streaming_system.system()),
+        );
+    }
+}
+
+/// Generates an endless cave system one chunk at a time, spawning the chunks around our player
+/// and despawning them again once they're far enough away.
+pub struct ChunkManager {
+    pub seed: u64,
+    /// the width and height of every chunk in tiles, at least 3 so our doorways have room between
+    /// our corners
+    pub chunk_size: usize,
+    /// we spawn any chunk whose center is closer to our player than this
+    pub spawn_radius: f32,
+    /// and despawn any chunk whose center is further than this
+    pub despawn_radius: f32,
+    pub fill_ratio: f64,
+    pub iterations: usize,
+    pub rules: CaveRules,
+    generated: HashMap<(i32, i32), Chunk>,
+    spawned: HashMap<(i32, i32), Entity>,
+}
+
+impl FromWorld for ChunkManager {
+    /// Our caves grow from their own stream of our `WorldSeed`
+    fn from_world(world: &mut World) -> Self {
+        let seed = world
+            .get_resource::<WorldSeed>()
+            .copied()
+            .unwrap_or_default();
+
+        ChunkManager {
+            seed: seed.derive("chunks"),
+            chunk_size: 16,
+            spawn_radius: 24.,
+            despawn_radius: 32.,
+            fill_ratio: 0.45,
+            iterations: 4,
+            rules: CaveRules::default(),
+            generated: HashMap::new(),
+            spawned: HashMap::new(),
+        }
+    }
+}
+
+impl ChunkManager {
+    /// The chunk containing this world position
+    pub fn chunk_coord(&self, grid: &LevelGrid, position: Vec3) -> (i32, i32) {
+        let (x, z) = grid.world_to_tile(position);
+        let size = self.chunk_size as i32;
+        (x.div_euclid(size), z.div_euclid(size))
+    }
+
+    /// The grid tile of the `(0, 0)` cell of a chunk
+    pub fn chunk_offset(&self, (chunk_x, chunk_z): (i32, i32)) -> (i32, i32) {
+        let size = self.chunk_size as i32;
+        (chunk_x * size, chunk_z * size)
+    }
+
+    /// The world position of the middle of a chunk
+    pub fn chunk_center(&self, grid: &LevelGrid, coord: (i32, i32)) -> Vec3 {
+        let half = self.chunk_size as f32 / 2.;
+        grid.tile_to_world(self.chunk_offset(coord))
+            + Vec3::new(half - 0.5, 0., half - 0.5) * grid.tile_size
+    }
+
+    /// Get the chunk at `coord`, generating it if we haven't seen it before
+    pub fn chunk(&mut self, coord: (i32, i32)) -> &Chunk {
+        if !self.generated.contains_key(&coord) {
+            let chunk = self.generate(coord);
+            self.generated.insert(coord, chunk);
+        }
+
+        &self.generated[&coord]
+    }
+
+    /// Find a walkable world position as close to the middle of the chunk at `coord` as we can
+    pub fn find_spawn(&mut self, grid: &LevelGrid, coord: (i32, i32)) -> Vec3 {
+        let (offset_x, offset_z) = self.chunk_offset(coord);
+        let chunk = self.chunk(coord);
+        let middle = (chunk.width() / 2, chunk.height() / 2);
+
+        let (x, z) = chunk
+            .cells()
+            .filter(|(_, _, tile)| tile.is_walkable())
+            .min_by_key(|(x, z, _)| {
+                let dx = *x as isize - middle.0 as isize;
+                let dz = *z as isize - middle.1 as isize;
+                dx * dx + dz * dz
+            })
+            .map_or((0, 0), |(x, z, _)| (x, z));
+
+        grid.tile_to_world((offset_x + x as i32, offset_z + z as i32))
+    }
+
+    /// Stitch together the chunks we've spawned in the rectangle between the chunks at `from` and
+    /// `to`, so we can find paths across their seams, along with the grid tile of its `(0, 0)` cell.
+    /// Anything we haven't spawned yet is left as wall, we can't walk there anyway.
+    pub fn loaded_region(&self, from: (i32, i32), to: (i32, i32)) -> (Chunk, (i32, i32)) {
+        let (min_x, max_x) = (from.0.min(to.0), from.0.max(to.0));
+        let (min_z, max_z) = (from.1.min(to.1), from.1.max(to.1));
+        let size = self.chunk_size;
+        let columns = (max_x - min_x + 1) as usize;
+        let rows = (max_z - min_z + 1) as usize;
+
+        let mut region = Chunk::new(columns * size, rows * size, Tile::Wall);
+        for chunk_z in min_z..=max_z {
+            for chunk_x in min_x..=max_x {
+                let coord = (chunk_x, chunk_z);
+                let chunk = match self.generated.get(&coord) {
+                    Some(chunk) if self.spawned.contains_key(&coord) => chunk,
+                    _ => continue,
+                };
+                let (left, top) = ((chunk_x - min_x) as usize, (chunk_z - min_z) as usize);
+                for (x, z, tile) in chunk.cells() {
+                    region.set(left * size + x, top * size + z, tile);
+                }
+            }
+        }
+
+        (region, self.chunk_offset((min_x, min_z)))
+    }
+
+    /// Look up a tile by its grid tile coordinate, if we've already generated its chunk
+    fn generated_tile(&self, x: i32, z: i32) -> Option<Tile> {
+        let size = self.chunk_size as i32;
+        let coord = (x.div_euclid(size), z.div_euclid(size));
+
+        self.generated
+            .get(&coord)
+            .and_then(|chunk| chunk.get(x.rem_euclid(size) as usize, z.rem_euclid(size) as usize))
+    }
+
+    /// Build the caves for a chunk. Our noise comes from hashing the world position of every cell
+    /// so we generate a margin around our chunk, smooth the whole thing and crop the margin off
+    /// again. The automaton only looks one cell away per iteration so a margin as wide as our
+    /// iteration count gives us exactly the same cells our neighbours see along our seams.
+    ///
+    /// We can't see past our margin, so we can't know which of our caves our neighbours join up.
+    /// Instead every seam gets a doorway somewhere along it picked from our seed, which both of the
+    /// chunks either side of it open up. Then we tunnel every cave of our chunk through to our
+    /// doorways, which only ever touches our own cells so our seams still match.
+    fn generate(&self, coord: (i32, i32)) -> Chunk {
+        let size = self.chunk_size;
+        let margin = self.iterations;
+        let start_x = coord.0 as i64 * size as i64 - margin as i64;
+        let start_z = coord.1 as i64 * size as i64 - margin as i64;
+
+        let mut padded = Chunk::new(size + margin * 2, size + margin * 2, Tile::Floor);
+        for z in 0..padded.height() {
+            for x in 0..padded.width() {
+                let wall =
+                    cell_noise(self.seed, start_x + x as i64, start_z + z as i64) < self.fill_ratio;
+                padded.set(x, z, Tile::from_floor(!wall));
+            }
+        }
+        for _ in 0..self.iterations {
+            padded.smooth(self.rules);
+        }
+
+        let mut chunk = Chunk::new(size, size, Tile::Wall);
+        for z in 0..size {
+            for x in 0..size {
+                if let Some(tile) = padded.get(x + margin, z + margin) {
+                    chunk.set(x, z, tile);
+                }
+            }
+        }
+
+        // north, east, south and then west, in the same place our neighbours open onto us
+        let last = size - 1;
+        let doorways = [
+            (self.doorway((coord.0, coord.1 - 1), Seam::South), 0),
+            (last, self.doorway(coord, Seam::East)),
+            (self.doorway(coord, Seam::South), last),
+            (0, self.doorway((coord.0 - 1, coord.1), Seam::East)),
+        ];
+        for (x, z) in doorways.iter() {
+            chunk.set(*x, *z, Tile::Floor);
+        }
+        chunk.make_connected(doorways[0], Repair::Tunnel);
+
+        chunk
+    }
+
+    /// How far along the seam on the `seam` side of the chunk at `coord` its doorway is. We keep
+    /// out of our corners, where our tunnels can't reach.
+    fn doorway(&self, coord: (i32, i32), seam: Seam) -> usize {
+        let seed = self.seed ^ seam as u64;
+        let along = cell_noise(seed, coord.0 as i64, coord.1 as i64);
+
+        1 + (along * (self.chunk_size - 2) as f64) as usize
+    }
+}
+
+/// The two seams every chunk owns, we share our north and west seams with our neighbours
+#[derive(Clone, Copy)]
+enum Seam {
+    East = 1,
+    South = 2,
+}
+
+/// A deterministic value in `[0, 1)` for every cell of our world, seeded by `seed`
+fn cell_noise(seed: u64, x: i64, z: i64) -> f64 {
+    // splitmix64 over our seed and coordinate so the same cell always gets the same value
+    let mut hash = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^= hash >> 31;
+
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn chunk_streaming_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    grid: Res<LevelGrid>,
+    wall_style: Res<WallStyle>,
+    mut manager: ResMut<ChunkManager>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+) {
+    let player_position = match player_query.iter().next() {
+        Some(transform) => transform.translation,
+        None => return,
+    };
+
+    let distance_to = |center: Vec3| {
+        Vec2::new(center.x - player_position.x, center.z - player_position.z).length()
+    };
+
+    // drop anything that's gotten too far away
+    let despawn_radius = manager.despawn_radius;
+    let far_away = manager
+        .spawned
+        .keys()
+        .filter(|coord| distance_to(manager.chunk_center(&grid, **coord)) > despawn_radius)
+        .cloned()
+        .collect::<Vec<_>>();
+    for coord in far_away {
+        if let Some(entity) = manager.spawned.remove(&coord) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    // and spawn anything that's come into range
+    let (player_x, player_z) = manager.chunk_coord(&grid, player_position);
+    let chunk_width = manager.chunk_size as f32 * grid.tile_size;
+    let reach = (manager.spawn_radius / chunk_width).ceil() as i32 + 1;
+    let gltf_handle = asset_server.load("models.gltf");
+    for chunk_z in player_z - reach..=player_z + reach {
+        for chunk_x in player_x - reach..=player_x + reach {
+            let coord = (chunk_x, chunk_z);
+            if manager.spawned.contains_key(&coord)
+                || distance_to(manager.chunk_center(&grid, coord)) > manager.spawn_radius
+            {
+                continue;
+            }
+
+            // our walls along every seam need to know what's on the other side, and our smooth
+            // walls leave the squares over our west and north seams to the chunks over there
+            for neighbour_z in chunk_z - 1..=chunk_z + 1 {
+                for neighbour_x in chunk_x - 1..=chunk_x + 1 {
+                    manager.chunk((neighbour_x, neighbour_z));
+                }
+            }
+
+            let (tile_x, tile_z) = manager.chunk_offset(coord);
+            let entity = spawn_chunk(
+                &mut commands,
+                &gltf_handle,
+                &grid,
+                *wall_style,
+                &manager.generated[&coord],
+                (tile_x, tile_z),
+                |x, z| manager.generated_tile(tile_x + x as i32, tile_z + z as i32),
+            )
+            .root;
+            manager.spawned.insert(coord, entity);
+        }
+    }
+
+    // forget about the chunks we've moved well away from, we can always generate them again
+    let forget_radius = despawn_radius + chunk_width;
+    let forgotten = manager
+        .generated
+        .keys()
+        .filter(|coord| distance_to(manager.chunk_center(&grid, **coord)) > forget_radius)
+        .cloned()
+        .collect::<Vec<_>>();
+    for coord in forgotten {
+        manager.generated.remove(&coord);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(seed: u64) -> ChunkManager {
+        ChunkManager {
+            seed,
+            chunk_size: 16,
+            spawn_radius: 24.,
+            despawn_radius: 32.,
+            fill_ratio: 0.45,
+            iterations: 4,
+            rules: CaveRules::default(),
+            generated: HashMap::new(),
+            spawned: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn neighbouring_chunks_are_connected() {
+        for seed in 0..8 {
+            let mut chunks = manager(seed);
+            let size = chunks.chunk_size;
+
+            // stitch a 3x3 block of chunks together, straddling our origin
+            let mut world = Chunk::new(size * 3, size * 3, Tile::Wall);
+            for chunk_z in -1..=1 {
+                for chunk_x in -1..=1 {
+                    let chunk = chunks.chunk((chunk_x, chunk_z)).clone();
+                    for (x, z, tile) in chunk.cells() {
+                        world.set(
+                            (chunk_x + 1) as usize * size + x,
+                            (chunk_z + 1) as usize * size + z,
+                            tile,
+                        );
+                    }
+                }
+            }
+
+            assert!(world.connectivity().is_connected(), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn chunks_open_onto_each_other_through_doorways() {
+        let mut chunks = manager(3);
+        let last = chunks.chunk_size - 1;
+        let east = chunks.doorway((0, 0), Seam::East);
+        let south = chunks.doorway((0, 0), Seam::South);
+
+        let chunk = chunks.chunk((0, 0)).clone();
+        assert_eq!(chunk.get(last, east), Some(Tile::Floor));
+        assert_eq!(chunk.get(south, last), Some(Tile::Floor));
+        assert_eq!(chunks.chunk((1, 0)).get(0, east), Some(Tile::Floor));
+        assert_eq!(chunks.chunk((0, 1)).get(south, 0), Some(Tile::Floor));
+    }
+
+    #[test]
+    fn chunks_arent_crossed_by_corridors() {
+        for seed in 0..8 {
+            let mut chunks = manager(seed);
+            let size = chunks.chunk_size;
+            let chunk = chunks.chunk((0, 0));
+            let middle = size / 2;
+
+            let open_row = (0..size).all(|x| chunk.get(x, middle) == Some(Tile::Floor));
+            let open_column = (0..size).all(|z| chunk.get(middle, z) == Some(Tile::Floor));
+            assert!(!(open_row && open_column), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn loaded_regions_stitch_our_spawned_chunks() {
+        let mut chunks = manager(5);
+        let size = chunks.chunk_size;
+        for coord in [(-1, 0), (0, 0), (0, 1), (-1, 1)].iter() {
+            chunks.chunk(*coord);
+        }
+        for (index, coord) in [(0, 0), (-1, 0), (0, 1)].iter().enumerate() {
+            chunks.spawned.insert(*coord, Entity::new(index as u32));
+        }
+
+        let (region, offset) = chunks.loaded_region((0, 1), (-1, 0));
+        assert_eq!((region.width(), region.height()), (size * 2, size * 2));
+        assert_eq!(offset, chunks.chunk_offset((-1, 0)));
+
+        for (coord, (left, top)) in [
+            ((-1, 0), (0, 0)),
+            ((0, 0), (size, 0)),
+            ((0, 1), (size, size)),
+        ]
+        .iter()
+        {
+            for (x, z, tile) in chunks.generated[coord].cells() {
+                assert_eq!(region.get(left + x, top + z), Some(tile));
+            }
+        }
+
+        // we generated the chunk south west of us but never spawned it
+        for z in size..size * 2 {
+            for x in 0..size {
+                assert_eq!(region.get(x, z), Some(Tile::Wall));
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_are_the_same_in_any_order() {
+        let mut forwards = manager(7);
+        let mut backwards = manager(7);
+        let coords = [(0, 0), (1, 0), (0, 1), (-1, -1)];
+
+        for coord in coords.iter() {
+            forwards.chunk(*coord);
+        }
+        for coord in coords.iter().rev() {
+            backwards.chunk(*coord);
+        }
+
+        for coord in coords.iter() {
+            assert_eq!(forwards.chunk(*coord), backwards.chunk(*coord));
+        }
+    }
+}
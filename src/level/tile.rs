@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// Everything a single cell of our level can be
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Tile {
+    /// nothing at all, the edge of the world
+    Void,
+    Floor,
+    Wall,
+    Water,
+    Lava,
+    Door,
+    /// a floor the player starts on
+    Spawn,
+}
+
+/// How a tile takes part in our physics simulation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TileCollider {
+    None,
+    /// blocks movement
+    Solid,
+    /// lets things through but reports intersections so gameplay can react
+    Sensor,
+}
+
+/// Tagged onto the sensors we spawn for tiles so intersection events can find out what was hit
+pub struct TileSensor(pub Tile);
+
+impl Tile {
+    /// Map our old floor / not floor grids onto tiles
+    pub fn from_floor(floor: bool) -> Tile {
+        if floor {
+            Tile::Floor
+        } else {
+            Tile::Wall
+        }
+    }
+
+    /// Can characters stand here, hazards are walkable but probably a bad idea
+    pub fn is_walkable(self) -> bool {
+        match self {
+            Tile::Floor | Tile::Lava | Tile::Door | Tile::Spawn => true,
+            Tile::Void | Tile::Wall | Tile::Water => false,
+        }
+    }
+
+    /// Hurts whatever stands on it, we'd rather walk around these
+    pub fn is_hazard(self) -> bool {
+        matches!(self, Tile::Lava)
+    }
+
+    /// We put walls between tiles that block and tiles that don't
+    pub fn blocks(self) -> bool {
+        matches!(self, Tile::Void | Tile::Wall)
+    }
+
+    /// The name of the mesh in `models.gltf` we lay down for this tile
+    pub fn floor_mesh(self) -> Option<&'static str> {
+        match self {
+            Tile::Floor | Tile::Door | Tile::Spawn => Some("grass"),
+            Tile::Water => Some("water"),
+            Tile::Lava => Some("lava"),
+            Tile::Void | Tile::Wall => None,
+        }
+    }
+
+    pub fn collider(self) -> TileCollider {
+        match self {
+            Tile::Water => TileCollider::Solid,
+            Tile::Lava | Tile::Door => TileCollider::Sensor,
+            Tile::Void | Tile::Floor | Tile::Wall | Tile::Spawn => TileCollider::None,
+        }
+    }
+}
+
+impl FromStr for Tile {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "void" => Tile::Void,
+            "floor" => Tile::Floor,
+            "wall" => Tile::Wall,
+            "water" => Tile::Water,
+            "lava" => Tile::Lava,
+            "door" => Tile::Door,
+            "spawn" => Tile::Spawn,
+            _ => bail!("Unknown tile \"{}\"", name),
+        })
+    }
+}
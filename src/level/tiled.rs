@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::level::{Chunk, Level, PlacedEntity, Tile};
+
+/// Tiled keeps its flip and rotation flags in the top bits of every tile id
+const TILE_ID_MASK: u32 = 0x0FFF_FFFF;
+
+/// What a tile from our Tiled tilesets becomes in our level
+#[derive(Clone, Debug)]
+pub struct TiledTile {
+    pub tile: Tile,
+    /// draw its floor with this mesh from `models.gltf` instead of our tile's usual one
+    pub mesh: Option<String>,
+}
+
+impl TiledTile {
+    pub fn new(tile: Tile) -> TiledTile {
+        TiledTile { tile, mesh: None }
+    }
+
+    pub fn with_mesh<S: ToString>(tile: Tile, mesh: S) -> TiledTile {
+        TiledTile {
+            tile,
+            mesh: Some(mesh.to_string()),
+        }
+    }
+}
+
+/// Maps the tile ids in our Tiled maps onto our tiles. These are the ids as they're stored in our
+/// maps, so the first tile of our first tileset is `1` and `0` is always an empty cell.
+///
+/// We only read this once, when our `LevelPlugin` sets up our Tiled loader.
+#[derive(Clone, Debug)]
+pub struct TiledTable {
+    pub tiles: HashMap<u32, TiledTile>,
+    /// anything we don't have an entry for
+    pub unknown: Tile,
+}
+
+impl Default for TiledTable {
+    fn default() -> Self {
+        TiledTable {
+            tiles: vec![
+                (1, TiledTile::new(Tile::Floor)),
+                (2, TiledTile::new(Tile::Wall)),
+                (3, TiledTile::new(Tile::Water)),
+                (4, TiledTile::new(Tile::Lava)),
+                (5, TiledTile::new(Tile::Door)),
+                (6, TiledTile::new(Tile::Spawn)),
+            ]
+            .into_iter()
+            .collect(),
+            unknown: Tile::Wall,
+        }
+    }
+}
+
+/// Loads Tiled maps saved as `.tmx` (xml) or `.tmj` (json). Our tile layers are stacked in order
+/// with empty cells letting the layers below show through. Every object on our object layers is
+/// placed on the tile under its center, a `spawn` object becomes our spawn tile and anything else
+/// becomes a `PlacedEntity` of its type (or name if it doesn't have one), so a `trigger` object is
+/// spawned by our `PlacedEntitySpawners` like any other trigger.
+pub struct TiledLevelLoader {
+    table: TiledTable,
+}
+
+impl FromWorld for TiledLevelLoader {
+    fn from_world(world: &mut World) -> Self {
+        TiledLevelLoader {
+            table: world
+                .get_resource::<TiledTable>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl AssetLoader for TiledLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let path = load_context.path();
+            let text = std::str::from_utf8(bytes)?;
+            let map = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("tmj") => parse_tmj(text),
+                _ => parse_tmx(text),
+            };
+            let level = map
+                .and_then(|map| map.into_level(&self.table))
+                .with_context(|| format!("Couldn't parse {}", path.display()))?;
+
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx", "tmj"]
+    }
+}
+
+/// The parts of a Tiled map we understand, whichever format it came from
+#[derive(Debug, Default)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    /// the tile ids of each of our tile layers, row by row
+    layers: Vec<Vec<u32>>,
+    objects: Vec<TiledObject>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TiledObject {
+    name: String,
+    #[serde(rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    /// tile objects are anchored at their bottom left instead of their top left
+    gid: Option<u32>,
+}
+
+impl TiledMap {
+    fn into_level(self, table: &TiledTable) -> anyhow::Result<Level> {
+        let mut chunk = Chunk::new(self.width, self.height, Tile::Void);
+        for (index, layer) in self.layers.iter().enumerate() {
+            if layer.len() != self.width * self.height {
+                bail!(
+                    "tile layer {} has {} tiles, expected {}",
+                    index + 1,
+                    layer.len(),
+                    self.width * self.height
+                );
+            }
+
+            for (cell, id) in layer.iter().enumerate() {
+                let id = id & TILE_ID_MASK;
+                if id == 0 {
+                    continue;
+                }
+
+                let (x, y) = (cell % self.width, cell / self.width);
+                match table.tiles.get(&id) {
+                    Some(TiledTile { tile, mesh }) => {
+                        chunk.set(x, y, *tile);
+                        chunk.set_floor_mesh(x, y, mesh.clone());
+                    }
+                    None => {
+                        chunk.set(x, y, table.unknown);
+                    }
+                }
+            }
+        }
+
+        let mut level = Level::new(chunk);
+        for object in self.objects {
+            let top = match object.gid {
+                Some(_) => object.y - object.height,
+                None => object.y,
+            };
+            let x = (object.x + object.width / 2.) / self.tile_width;
+            let y = (top + object.height / 2.) / self.tile_height;
+            if x < 0. || y < 0. || level.chunk.get(x as usize, y as usize).is_none() {
+                bail!(
+                    "object \"{}\" at ({}, {}) is outside of our map",
+                    object.name,
+                    object.x,
+                    object.y
+                );
+            }
+            let tile = (x as usize, y as usize);
+
+            let kind = if object.kind.is_empty() {
+                object.name
+            } else {
+                object.kind
+            };
+            if kind.eq_ignore_ascii_case("spawn") {
+                level.chunk.set(tile.0, tile.1, Tile::Spawn);
+            } else {
+                level.entities.push(PlacedEntity { kind, tile });
+            }
+        }
+
+        Ok(level)
+    }
+}
+
+fn parse_tmx(text: &str) -> anyhow::Result<TiledMap> {
+    let document = roxmltree::Document::parse(text)?;
+    let root = document.root_element();
+    if root.tag_name().name() != "map" {
+        bail!("expected a <map>, found <{}>", root.tag_name().name());
+    }
+    if root.attribute("infinite") == Some("1") {
+        bail!("we don't support infinite maps");
+    }
+
+    let mut map = TiledMap {
+        width: attribute(&root, "width")?,
+        height: attribute(&root, "height")?,
+        tile_width: attribute(&root, "tilewidth")?,
+        tile_height: attribute(&root, "tileheight")?,
+        ..Default::default()
+    };
+
+    add_tmx_layers(&mut map, &root)?;
+
+    Ok(map)
+}
+
+/// Read the layers of our map or a group, layers can be nested in groups. Our tilesets have object
+/// groups of their own for their tiles' collision shapes, so we only look at our layers.
+fn add_tmx_layers(map: &mut TiledMap, parent: &roxmltree::Node) -> anyhow::Result<()> {
+    for node in parent.children() {
+        match node.tag_name().name() {
+            "layer" => {
+                let data = node
+                    .children()
+                    .find(|child| child.has_tag_name("data"))
+                    .ok_or_else(|| anyhow!("line {}: layer without any data", line(&node)))?;
+                map.layers.push(parse_tmx_data(&data)?);
+            }
+            "objectgroup" => {
+                for object in node.children().filter(|child| child.has_tag_name("object")) {
+                    map.objects.push(parse_tmx_object(&object)?);
+                }
+            }
+            "group" => add_tmx_layers(map, &node)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_tmx_object(node: &roxmltree::Node) -> anyhow::Result<TiledObject> {
+    Ok(TiledObject {
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        kind: node
+            .attribute("type")
+            .or_else(|| node.attribute("class"))
+            .unwrap_or_default()
+            .to_string(),
+        x: optional_attribute(node, "x")?.unwrap_or_default(),
+        y: optional_attribute(node, "y")?.unwrap_or_default(),
+        width: optional_attribute(node, "width")?.unwrap_or_default(),
+        height: optional_attribute(node, "height")?.unwrap_or_default(),
+        gid: optional_attribute(node, "gid")?,
+    })
+}
+
+fn parse_tmx_data(data: &roxmltree::Node) -> anyhow::Result<Vec<u32>> {
+    match data.attribute("encoding") {
+        Some("csv") => data
+            .text()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|id| !id.trim().is_empty())
+            .map(|id| {
+                id.trim()
+                    .parse()
+                    .with_context(|| format!("line {}: bad tile id '{}'", line(data), id.trim()))
+            })
+            .collect(),
+        // no encoding means a <tile gid=".."/> per cell
+        None => data
+            .children()
+            .filter(|child| child.has_tag_name("tile"))
+            .map(|tile| Ok(optional_attribute(&tile, "gid")?.unwrap_or(0)))
+            .collect(),
+        Some(encoding) => bail!(
+            "line {}: we only read csv layers, not {}",
+            line(data),
+            encoding
+        ),
+    }
+}
+
+fn attribute<T: std::str::FromStr>(node: &roxmltree::Node, name: &str) -> anyhow::Result<T> {
+    optional_attribute(node, name)?.ok_or_else(|| {
+        anyhow!(
+            "line {}: <{}> is missing \"{}\"",
+            line(node),
+            node.tag_name().name(),
+            name
+        )
+    })
+}
+
+fn optional_attribute<T: std::str::FromStr>(
+    node: &roxmltree::Node,
+    name: &str,
+) -> anyhow::Result<Option<T>> {
+    node.attribute(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("line {}: bad {} '{}'", line(node), name, value))
+        })
+        .transpose()
+}
+
+fn line(node: &roxmltree::Node) -> u32 {
+    node.document().text_pos_at(node.range().start).row
+}
+
+/// The parts of a `.tmj` we read
+#[derive(Deserialize)]
+struct TmjMap {
+    width: usize,
+    height: usize,
+    #[serde(rename = "tilewidth")]
+    tile_width: f32,
+    #[serde(rename = "tileheight")]
+    tile_height: f32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TmjLayer>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TmjLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        data: TmjData,
+        encoding: Option<String>,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects { objects: Vec<TiledObject> },
+    #[serde(rename = "group")]
+    Group { layers: Vec<TmjLayer> },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmjData {
+    Ids(Vec<u32>),
+    /// base64, which we don't read
+    Encoded(String),
+}
+
+fn parse_tmj(text: &str) -> anyhow::Result<TiledMap> {
+    let tmj: TmjMap = serde_json::from_str(text)?;
+    if tmj.infinite {
+        bail!("we don't support infinite maps");
+    }
+
+    let mut map = TiledMap {
+        width: tmj.width,
+        height: tmj.height,
+        tile_width: tmj.tile_width,
+        tile_height: tmj.tile_height,
+        ..Default::default()
+    };
+    add_tmj_layers(&mut map, tmj.layers)?;
+
+    Ok(map)
+}
+
+fn add_tmj_layers(map: &mut TiledMap, layers: Vec<TmjLayer>) -> anyhow::Result<()> {
+    for layer in layers {
+        match layer {
+            TmjLayer::Tiles {
+                data: TmjData::Ids(ids),
+                ..
+            } => map.layers.push(ids),
+            TmjLayer::Tiles {
+                data: TmjData::Encoded(_),
+                encoding,
+            } => bail!(
+                "we only read csv layers, not {}",
+                encoding.unwrap_or_default()
+            ),
+            TmjLayer::Objects { objects } => map.objects.extend(objects),
+            TmjLayer::Group { layers } => add_tmj_layers(map, layers)?,
+            TmjLayer::Other => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn courtyard(map: anyhow::Result<TiledMap>) -> Level {
+        map.and_then(|map| map.into_level(&TiledTable::default()))
+            .unwrap()
+    }
+
+    fn placed(kind: &str, tile: (usize, usize)) -> PlacedEntity {
+        PlacedEntity {
+            kind: kind.to_string(),
+            tile,
+        }
+    }
+
+    #[test]
+    fn tmx_and_tmj_maps_match() {
+        let tmx = courtyard(parse_tmx(include_str!("../../assets/levels/courtyard.tmx")));
+        let tmj = courtyard(parse_tmj(include_str!("../../assets/levels/courtyard.tmj")));
+
+        assert_eq!(tmx, tmj);
+        assert_eq!((tmx.chunk.width(), tmx.chunk.height()), (5, 4));
+        assert_eq!(tmx.chunk.get(0, 0), Some(Tile::Wall));
+        assert_eq!(tmx.chunk.get(2, 2), Some(Tile::Water));
+        assert_eq!(tmx.spawn(), Some((1, 1)));
+        // the collision shape in our tileset isn't one of our objects
+        assert_eq!(
+            tmx.entities,
+            vec![
+                placed("trigger", (3, 2)),
+                placed("light", (2, 1)),
+                placed("chest", (1, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn objects_outside_our_map_are_rejected() {
+        let map = TiledMap {
+            width: 2,
+            height: 2,
+            tile_width: 32.,
+            tile_height: 32.,
+            layers: vec![vec![1; 4]],
+            objects: vec![TiledObject {
+                name: "chest".to_string(),
+                x: 80.,
+                y: 16.,
+                ..Default::default()
+            }],
+        };
+
+        assert!(map.into_level(&TiledTable::default()).is_err());
+    }
+}
@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use rand::Rng;
+
+use crate::level::{Chunk, Tile};
+
+/// A user supplied tile id, the solver doesn't care what these mean
+pub type TileId = usize;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Direction {
+    /// towards `y - 1`
+    North,
+    /// towards `x + 1`
+    East,
+    /// towards `y + 1`
+    South,
+    /// towards `x - 1`
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub(super) fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::East => (1, 0),
+            Direction::South => (0, 1),
+            Direction::West => (-1, 0),
+        }
+    }
+}
+
+/// A tile our solver can place, how likely it is to be picked and what can sit next to it
+#[derive(Clone, Debug)]
+pub struct TileRule {
+    pub id: TileId,
+    pub weight: f64,
+    /// the tiles allowed next to us, indexed by `Direction`
+    pub neighbours: [Vec<TileId>; 4],
+}
+
+impl TileRule {
+    pub fn new(id: TileId, weight: f64) -> TileRule {
+        TileRule {
+            id,
+            weight,
+            neighbours: Default::default(),
+        }
+    }
+
+    pub fn allow(mut self, direction: Direction, tiles: &[TileId]) -> TileRule {
+        self.neighbours[direction.index()].extend_from_slice(tiles);
+        self
+    }
+}
+
+/// Our tiles along with their adjacency rules, resolved into indices for the solver
+pub struct Tileset {
+    ids: Vec<TileId>,
+    weights: Vec<f64>,
+    /// `compatible[direction][a][b]` is true if `b` can sit in `direction` of `a`
+    compatible: [Vec<Vec<bool>>; 4],
+}
+
+impl Tileset {
+    /// Build a tileset from our rules. A neighbour allowed by either side of a pairing is allowed
+    /// by both so our rules don't need to be written out twice.
+    pub fn new(rules: &[TileRule]) -> anyhow::Result<Tileset> {
+        let mut indices = HashMap::new();
+        for (index, rule) in rules.iter().enumerate() {
+            if indices.insert(rule.id, index).is_some() {
+                bail!("Tile {} was defined more than once", rule.id);
+            }
+            // written so a NaN weight fails too
+            if !(rule.weight.is_finite() && rule.weight > 0.) {
+                bail!("Tile {} needs a positive, finite weight", rule.id);
+            }
+        }
+
+        let mut compatible: [Vec<Vec<bool>>; 4] = Default::default();
+        for direction in compatible.iter_mut() {
+            *direction = vec![vec![false; rules.len()]; rules.len()];
+        }
+
+        for (a, rule) in rules.iter().enumerate() {
+            for &direction in Direction::ALL.iter() {
+                for neighbour in rule.neighbours[direction.index()].iter() {
+                    let b = match indices.get(neighbour) {
+                        Some(b) => *b,
+                        None => bail!("Tile {} references unknown tile {}", rule.id, neighbour),
+                    };
+                    compatible[direction.index()][a][b] = true;
+                    compatible[direction.opposite().index()][b][a] = true;
+                }
+            }
+        }
+
+        Ok(Tileset {
+            ids: rules.iter().map(|rule| rule.id).collect(),
+            weights: rules.iter().map(|rule| rule.weight).collect(),
+            compatible,
+        })
+    }
+
+    /// Learn our tiles from a small hand authored example, `example[y][x]`. Every pairing we see
+    /// becomes an allowed neighbour and tiles are weighted by how often they show up.
+    pub fn from_example(example: &[Vec<TileId>]) -> anyhow::Result<Tileset> {
+        let mut rules: Vec<TileRule> = vec![];
+        let mut indices = HashMap::new();
+        for &id in example.iter().flatten() {
+            let index = *indices.entry(id).or_insert_with(|| {
+                rules.push(TileRule::new(id, 0.));
+                rules.len() - 1
+            });
+            rules[index].weight += 1.;
+        }
+
+        for (y, row) in example.iter().enumerate() {
+            for (x, &id) in row.iter().enumerate() {
+                for &direction in Direction::ALL.iter() {
+                    let (dx, dy) = direction.offset();
+                    let neighbour = example
+                        .get((y as isize + dy) as usize)
+                        .and_then(|row| row.get((x as isize + dx) as usize));
+
+                    if let Some(&neighbour) = neighbour {
+                        let allowed = &mut rules[indices[&id]].neighbours[direction.index()];
+                        if !allowed.contains(&neighbour) {
+                            allowed.push(neighbour);
+                        }
+                    }
+                }
+            }
+        }
+
+        Tileset::new(&rules)
+    }
+
+    /// Collapse a `width` x `height` grid, backtracking out of contradictions at most
+    /// `max_backtracks` times. The result is indexed `grid[y][x]`.
+    pub fn solve<R: Rng>(
+        &self,
+        rng: &mut R,
+        width: usize,
+        height: usize,
+        max_backtracks: usize,
+    ) -> anyhow::Result<Vec<Vec<TileId>>> {
+        if self.ids.is_empty() {
+            bail!("Can't solve with an empty tileset");
+        }
+
+        let mut wave = Wave {
+            width,
+            height,
+            cells: vec![vec![true; self.ids.len()]; width * height],
+        };
+
+        // our rules can rule options out before we've made a single choice, i.e. a tile that
+        // can't sit above itself, and cells with one option left never get chosen
+        for cell in 0..width * height {
+            if !self.propagate(&mut wave, cell) {
+                bail!(
+                    "This tileset has no solution for a {}x{} grid",
+                    width,
+                    height
+                );
+            }
+        }
+
+        // every choice we've made along with the wave from right before we made it
+        let mut history: Vec<(Vec<Vec<bool>>, usize, usize)> = vec![];
+        let mut backtracks = 0;
+
+        while let Some(cell) = self.lowest_entropy(rng, &wave) {
+            let tile = self.pick_tile(rng, &wave.cells[cell]);
+
+            history.push((wave.cells.clone(), cell, tile));
+            for (option, possible) in wave.cells[cell].iter_mut().enumerate() {
+                *possible = option == tile;
+            }
+
+            let mut consistent = self.propagate(&mut wave, cell);
+            while !consistent {
+                backtracks += 1;
+                if backtracks > max_backtracks {
+                    bail!("Gave up after {} backtracks", max_backtracks);
+                }
+
+                // rewind to before our last choice and rule it out
+                let (cells, cell, tile) = match history.pop() {
+                    Some(choice) => choice,
+                    None => bail!(
+                        "This tileset has no solution for a {}x{} grid",
+                        width,
+                        height
+                    ),
+                };
+                wave.cells = cells;
+                wave.cells[cell][tile] = false;
+
+                consistent = wave.cells[cell].iter().any(|possible| *possible)
+                    && self.propagate(&mut wave, cell);
+            }
+        }
+
+        Ok(wave
+            .cells
+            .chunks(width.max(1))
+            .map(|row| {
+                row.iter()
+                    .map(|options| self.ids[options.iter().position(|p| *p).unwrap()])
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Find the unresolved cell with the fewest options left, breaking ties randomly
+    fn lowest_entropy<R: Rng>(&self, rng: &mut R, wave: &Wave) -> Option<usize> {
+        let mut lowest = None;
+        let mut lowest_entropy = f64::MAX;
+        for (cell, options) in wave.cells.iter().enumerate() {
+            if options.iter().filter(|p| **p).count() <= 1 {
+                continue;
+            }
+
+            let (total, weighted_log) = options
+                .iter()
+                .zip(self.weights.iter())
+                .filter(|(possible, _)| **possible)
+                .fold((0., 0.), |(total, weighted_log), (_, &weight)| {
+                    (total + weight, weighted_log + weight * f64::ln(weight))
+                });
+            let entropy = f64::ln(total) - weighted_log / total + rng.gen_range(0. ..1e-6);
+
+            if entropy < lowest_entropy {
+                lowest_entropy = entropy;
+                lowest = Some(cell);
+            }
+        }
+
+        lowest
+    }
+
+    fn pick_tile<R: Rng>(&self, rng: &mut R, options: &[bool]) -> usize {
+        let total: f64 = options
+            .iter()
+            .zip(self.weights.iter())
+            .filter(|(possible, _)| **possible)
+            .map(|(_, weight)| weight)
+            .sum();
+
+        let mut remaining = rng.gen_range(0. ..total);
+        let mut last = 0;
+        for (tile, (possible, weight)) in options.iter().zip(self.weights.iter()).enumerate() {
+            if *possible {
+                if remaining < *weight {
+                    return tile;
+                }
+                remaining -= weight;
+                last = tile;
+            }
+        }
+
+        // floating point could leave us a hair past our last option
+        last
+    }
+
+    /// Spread the consequences of changing `start` across the wave, false on a contradiction
+    fn propagate(&self, wave: &mut Wave, start: usize) -> bool {
+        let mut open = vec![start];
+        while let Some(cell) = open.pop() {
+            for &direction in Direction::ALL.iter() {
+                let neighbour = match wave.neighbour(cell, direction) {
+                    Some(neighbour) => neighbour,
+                    None => continue,
+                };
+
+                // anything in our neighbour that none of our options allow has to go
+                let compatible = &self.compatible[direction.index()];
+                let mut allowed = vec![false; self.ids.len()];
+                for (tile, _) in wave.cells[cell].iter().enumerate().filter(|(_, p)| **p) {
+                    for (option, allow) in allowed.iter_mut().enumerate() {
+                        *allow |= compatible[tile][option];
+                    }
+                }
+
+                let mut changed = false;
+                for (possible, allow) in wave.cells[neighbour].iter_mut().zip(allowed) {
+                    if *possible && !allow {
+                        *possible = false;
+                        changed = true;
+                    }
+                }
+
+                if changed {
+                    if !wave.cells[neighbour].iter().any(|possible| *possible) {
+                        return false;
+                    }
+                    open.push(neighbour);
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// The options still open to each cell of our grid
+struct Wave {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<bool>>,
+}
+
+impl Wave {
+    fn neighbour(&self, cell: usize, direction: Direction) -> Option<usize> {
+        let (dx, dy) = direction.offset();
+        let x = (cell % self.width) as isize + dx;
+        let y = (cell / self.width) as isize + dy;
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            None
+        } else {
+            Some(y as usize * self.width + x as usize)
+        }
+    }
+}
+
+impl Chunk {
+    /// Fill our chunk with the wave function collapse solver, `to_tile` maps our solved ids onto
+    /// the tiles we'll build
+    pub fn wfc<R: Rng, F: Fn(TileId) -> Tile>(
+        rng: &mut R,
+        width: usize,
+        height: usize,
+        tileset: &Tileset,
+        max_backtracks: usize,
+        to_tile: F,
+    ) -> anyhow::Result<Chunk> {
+        let tiles = tileset.solve(rng, width, height, max_backtracks)?;
+
+        let mut chunk = Chunk::new(width, height, Tile::Void);
+        for (y, row) in tiles.iter().enumerate() {
+            for (x, id) in row.iter().enumerate() {
+                chunk.set(x, y, to_tile(*id));
+            }
+        }
+
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    const SEA: TileId = 0;
+    const COAST: TileId = 1;
+    const LAND: TileId = 2;
+
+    /// Sea only meets land through a coast
+    fn islands() -> Tileset {
+        let everywhere = |rule: TileRule, tiles: &[TileId]| {
+            Direction::ALL
+                .iter()
+                .fold(rule, |rule, direction| rule.allow(*direction, tiles))
+        };
+
+        Tileset::new(&[
+            everywhere(TileRule::new(SEA, 3.), &[SEA, COAST]),
+            everywhere(TileRule::new(COAST, 1.), &[COAST, LAND]),
+            everywhere(TileRule::new(LAND, 2.), &[LAND]),
+        ])
+        .unwrap()
+    }
+
+    /// Check every pair of neighbours in our solution is one our tileset allows. Our ids are the
+    /// same as our indices in these tests.
+    fn assert_follows_rules(tileset: &Tileset, solution: &[Vec<TileId>]) {
+        for (y, row) in solution.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                if let Some(&east) = row.get(x + 1) {
+                    assert!(
+                        tileset.compatible[Direction::East.index()][tile][east],
+                        "{} can't be west of {} at ({}, {})",
+                        tile,
+                        east,
+                        x,
+                        y
+                    );
+                }
+                if let Some(&south) = solution.get(y + 1).map(|row| &row[x]) {
+                    assert!(
+                        tileset.compatible[Direction::South.index()][tile][south],
+                        "{} can't be north of {} at ({}, {})",
+                        tile,
+                        south,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solutions_follow_our_rules() {
+        let tileset = islands();
+        for seed in 0..8 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let solution = tileset.solve(&mut rng, 12, 9, 100).unwrap();
+
+            assert_eq!(solution.len(), 9);
+            assert!(solution.iter().all(|row| row.len() == 12));
+            assert_follows_rules(&tileset, &solution);
+        }
+    }
+
+    #[test]
+    fn solutions_are_seeded() {
+        let tileset = islands();
+        let solve = |seed| {
+            tileset
+                .solve(&mut ChaCha8Rng::seed_from_u64(seed), 10, 10, 100)
+                .unwrap()
+        };
+
+        assert_eq!(solve(3), solve(3));
+    }
+
+    #[test]
+    fn examples_teach_us_our_rules() {
+        let example = vec![
+            vec![SEA, SEA, COAST, LAND],
+            vec![SEA, COAST, LAND, LAND],
+            vec![COAST, LAND, LAND, LAND],
+        ];
+        let tileset = Tileset::from_example(&example).unwrap();
+        assert_eq!(tileset.ids, vec![SEA, COAST, LAND]);
+        assert!(!tileset.compatible[Direction::East.index()][SEA][LAND]);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let solution = tileset.solve(&mut rng, 8, 8, 100).unwrap();
+        assert_follows_rules(&tileset, &solution);
+    }
+
+    #[test]
+    fn impossible_grids_fail() {
+        // we only ever allow our tile beside itself horizontally, so we can't stack 2 rows
+        let tileset = Tileset::new(&[TileRule::new(0, 1.)
+            .allow(Direction::East, &[0])
+            .allow(Direction::West, &[0])])
+        .unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        assert!(tileset.solve(&mut rng, 3, 1, 10).is_ok());
+        assert!(tileset.solve(&mut rng, 3, 2, 10).is_err());
+    }
+
+    #[test]
+    fn bad_rules_are_rejected() {
+        assert!(Tileset::new(&[TileRule::new(0, 1.), TileRule::new(0, 1.)]).is_err());
+        assert!(Tileset::new(&[TileRule::new(0, 0.)]).is_err());
+        assert!(Tileset::new(&[TileRule::new(0, -1.)]).is_err());
+        assert!(Tileset::new(&[TileRule::new(0, f64::NAN)]).is_err());
+        assert!(Tileset::new(&[TileRule::new(0, f64::INFINITY)]).is_err());
+        assert!(Tileset::new(&[TileRule::new(0, 1.).allow(Direction::North, &[7])]).is_err());
+
+        let empty = Tileset::new(&[]).unwrap();
+        assert!(empty
+            .solve(&mut ChaCha8Rng::seed_from_u64(0), 2, 2, 10)
+            .is_err());
+    }
+}
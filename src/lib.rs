@@ -1,5 +1,3 @@
-use std::f32::consts::FRAC_PI_2;
-
 use bevy::input::system::exit_on_esc_system;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
@@ -8,19 +6,26 @@ use wasm_bindgen::prelude::*;
 use crate::aim_system::{aim_system, MouseLightBundle};
 use crate::debug::Debug;
 use crate::debug_physics::DebugPhysicsPlugin;
-use crate::level::Chunk;
+use crate::editor::EditorPlugin;
+use crate::level::{ChunkManager, CurrentLevel, Level, LevelGrid, LevelPlugin, LevelSource};
+use crate::markers::MarkerPlugin;
 use crate::mesh_loader::{MeshLoaderPlugin, SpawnMeshAsChildCommands};
-use crate::movement::MovePlugin;
+use crate::movement::{MovePlugin, PathFollower};
 use crate::player::{Player, PlayerControlled};
+use crate::seed::{SeedPlugin, WorldSeed};
 use crate::view_system::{UiCam, ViewPlugin};
 
 mod aim_system;
+mod args;
 mod debug;
 mod debug_physics;
+mod editor;
 mod level;
+mod markers;
 mod mesh_loader;
 mod movement;
 mod player;
+mod seed;
 mod view_system;
 
 #[derive(Default)]
@@ -28,13 +33,30 @@ struct Game {}
 
 #[wasm_bindgen]
 pub fn run() {
-    default_plugins(&mut App::build())
+    run_app(App::build())
+}
+
+/// Run our game in the world grown from `seed`
+#[wasm_bindgen]
+pub fn run_with_seed(seed: u64) {
+    let mut app = App::build();
+    app.insert_resource(WorldSeed(seed));
+    run_app(app)
+}
+
+fn run_app(mut app: AppBuilder) {
+    default_plugins(&mut app)
         .add_system(exit_on_esc_system.system())
         .add_startup_system(setup.system())
         .add_plugin(ViewPlugin)
         .add_plugin(MovePlugin)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugin(MeshLoaderPlugin)
+        .add_plugin(SeedPlugin)
+        .insert_resource(LevelSource::from_args())
+        .add_plugin(LevelPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(MarkerPlugin)
         .add_system(aim_system.system())
         // diagnostics
         .add_plugin(Debug)
@@ -55,7 +77,15 @@ fn default_plugins(builder: &mut AppBuilder) -> &mut AppBuilder {
         .add_plugin(bevy_prototype_debug_lines::DebugLinesPlugin)
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut chunks: ResMut<ChunkManager>,
+    mut levels: ResMut<Assets<Level>>,
+    grid: Res<LevelGrid>,
+    level_source: Res<LevelSource>,
+    seed: Res<WorldSeed>,
+) {
     // Tell the asset server to watch for asset changes on disk (I'm not sure this actually works)
     asset_server.watch_for_changes().unwrap();
 
@@ -75,6 +105,24 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 
     let gltf_handle = asset_server.load("models.gltf");
 
+    // load our level, or start our character somewhere open in the middle of our streamed world
+    let spawn = match &*level_source {
+        LevelSource::Streamed => chunks.find_spawn(&grid, (0, 0)),
+        LevelSource::Caves | LevelSource::Dungeon => {
+            let level = level_source.generate(*seed).unwrap();
+            commands.insert_resource(CurrentLevel::new(levels.add(level)));
+
+            // our level spawns just like an asset, and moves our character onto its spawn
+            grid.origin
+        }
+        LevelSource::Asset(path) => {
+            commands.insert_resource(CurrentLevel::new(asset_server.load(path.as_str())));
+
+            // we'll move our character onto the level's spawn once it's loaded
+            grid.origin
+        }
+    };
+
     // add our character
 
     // let character_handle = asset_server.load("models.gltf#Mesh0");
@@ -86,13 +134,14 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         })
         .insert(Player)
         .insert(PlayerControlled)
+        .insert(PathFollower::default())
         .insert_bundle(RigidBodyBundle {
             activation: RigidBodyActivation {
                 sleeping: false,
                 ..Default::default()
             },
             body_type: RigidBodyType::Dynamic,
-            position: Vec3::new(0.0, 5.0, 0.0).into(),
+            position: Vec3::new(spawn.x, 5.0, spawn.z).into(),
             ..Default::default()
         })
         .insert_bundle(ColliderBundle {
@@ -103,81 +152,4 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ..Default::default()
         })
         .insert(ColliderPositionSync::Discrete);
-
-    // build our map
-    const WIDTH: usize = 20;
-    const HEIGHT: usize = 20;
-    let chunk = Chunk::<WIDTH, HEIGHT>::arena();
-
-    let x_offset = (WIDTH / 2) as f32;
-    let z_offset = (HEIGHT / 2) as f32;
-    for z in 0..HEIGHT {
-        for x in 0..WIDTH {
-            let position = Vector::new(x as f32 - x_offset, 0., z as f32 - z_offset);
-
-            for (dx, dz, rotate) in [(1, 0, false), (0, 1, true)].iter() {
-                let nx = x + dx;
-                let nz = z + dz;
-                if nx < WIDTH && nz < HEIGHT && chunk.grid[z][x] != chunk.grid[nz][nx] {
-                    // todo this is gross
-                    let wall_transform: Isometry<Real> = if *rotate {
-                        Isometry::new(position, Vector::y() * FRAC_PI_2)
-                    } else {
-                        position.into()
-                    };
-
-                    // todo why don't our walls work?
-                    commands
-                        .spawn_bundle((
-                            Transform {
-                                translation: wall_transform.translation.into(),
-                                rotation: wall_transform.rotation.into(),
-                                ..Default::default()
-                            },
-                            GlobalTransform::identity(),
-                        ))
-                        .insert("Wall".to_string())
-                        .with_children(|builder| {
-                            builder.spawn_mesh(gltf_handle.clone(), "wall", true);
-                        })
-                        // .insert_bundle(RigidBodyBundle {
-                        //     body_type: RigidBodyType::Static,
-                        //     // activation: RigidBodyActivation {
-                        //     //     sleeping: false,
-                        //     //     ..Default::default()
-                        //     // },
-                        //     position: wall_transform.into(),
-                        //     ..Default::default()
-                        // })
-                        .insert_bundle(ColliderBundle {
-                            shape: ColliderShape::ball(0.5), // give ourselves a dummy shape while we derive from our mesh
-                            collider_type: ColliderType::Solid,
-                            position: wall_transform.into(),
-                            ..Default::default()
-                        });
-                }
-            }
-            if chunk.grid[z][x] {
-                commands
-                    .spawn_bundle((
-                        Transform::from_translation(position.into()),
-                        GlobalTransform::identity(),
-                    ))
-                    .insert("Floor".to_string())
-                    .with_children(|builder| {
-                        builder.spawn_mesh(gltf_handle.clone(), "grass", false);
-                    });
-            }
-        }
-    }
-
-    commands.spawn_bundle(ColliderBundle {
-        shape: ColliderShape::cuboid(WIDTH as f32, 0.1, HEIGHT as f32),
-        // todo use a height field ?
-        // shape: ColliderShape::heightfield(
-        //     DMatrix::from_vec(WIDTH, HEIGHT, vec![0.; WIDTH * HEIGHT]),
-        //     Vector::new(1., 1., 1.),
-        // ),
-        ..Default::default()
-    });
 }
@@ -0,0 +1,266 @@
+use bevy::ecs::system::EntityCommands;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::level::{CurrentLevel, Level};
+use crate::mesh_loader::{
+    ExtrasContext, ExtrasHandlers, GltfMarker, GltfMarkers, SpawnMeshAsChildCommands, MARKERS_LABEL,
+};
+use crate::player::{Enemy, Player};
+
+pub struct MarkerPlugin;
+
+impl Plugin for MarkerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(marker_spawn_system.system());
+    }
+}
+
+/// Spawns things at the markers in the gltf named by our level's `markers`:
+///  - `spawn_player` moves our player, overriding our level's spawn
+///  - `spawn_enemy_*` spawns an enemy
+///  - `light_*` spawns a light
+///
+/// Anything we spawn also gets the components from the custom properties on its marker. Our
+/// streamed world doesn't have a level, so it doesn't have any markers either.
+#[derive(Default)]
+struct MarkerState {
+    /// the gltf our current level takes its markers from
+    path: Option<String>,
+    handle: Option<Handle<GltfMarkers>>,
+    /// our markers have changed and we haven't spawned them yet
+    pending: bool,
+    spawned: Vec<Entity>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn marker_spawn_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut state: Local<MarkerState>,
+    mut level_events: EventReader<AssetEvent<Level>>,
+    mut marker_events: EventReader<AssetEvent<GltfMarkers>>,
+    levels: Res<Assets<Level>>,
+    markers: Res<Assets<GltfMarkers>>,
+    extras_handlers: Res<ExtrasHandlers>,
+    current_level: Option<Res<CurrentLevel>>,
+    mut player_query: Query<&mut RigidBodyPosition, With<Player>>,
+) {
+    let current_level = match current_level {
+        Some(current_level) => current_level,
+        None => return,
+    };
+
+    // a new level starts our markers over, an edited one only if it points at another gltf
+    for event in level_events.iter() {
+        let (handle, created) = match event {
+            AssetEvent::Created { handle } => (handle, true),
+            AssetEvent::Modified { handle } => (handle, false),
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != current_level.handle {
+            continue;
+        }
+
+        let path = levels.get(handle).and_then(|level| level.markers.clone());
+        if created || path != state.path {
+            state.handle = path
+                .as_ref()
+                .map(|path| asset_server.load(format!("{}#{}", path, MARKERS_LABEL).as_str()));
+            state.path = path;
+            state.pending = true;
+        }
+    }
+
+    for event in marker_events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed } => {
+                if Some(changed) == state.handle.as_ref() {
+                    state.pending = true;
+                }
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    // wait for our level to spawn so we're the last to move our player
+    if !state.pending || current_level.chunk().is_none() {
+        return;
+    }
+    let markers = match &state.handle {
+        Some(handle) => match markers.get(handle) {
+            Some(markers) => Some(markers),
+            None => return,
+        },
+        None => None,
+    };
+    state.pending = false;
+
+    for entity in state.spawned.drain(..) {
+        commands.entity(entity).despawn_recursive();
+    }
+    let markers = match markers {
+        Some(markers) => markers,
+        None => return,
+    };
+
+    if let Some(marker) = markers.get("spawn_player") {
+        let spawn = marker.transform.translation;
+        for mut position in player_query.iter_mut() {
+            position.position = Isometry::translation(spawn.x, spawn.y, spawn.z);
+            position.next_position = position.position;
+        }
+    }
+
+    let gltf_handle = asset_server.load("models.gltf");
+    state.spawned = spawn_markers(&mut commands, markers, &gltf_handle, &extras_handlers);
+}
+
+/// Spawn an enemy at each of our `spawn_enemy_*` markers and a light at each `light_*`, returning
+/// everything we spawned
+fn spawn_markers(
+    commands: &mut Commands,
+    markers: &GltfMarkers,
+    gltf_handle: &Handle<Gltf>,
+    extras_handlers: &ExtrasHandlers,
+) -> Vec<Entity> {
+    let mut spawned = vec![];
+    for marker in markers.starting_with("spawn_enemy_") {
+        let mut entity_commands =
+            commands.spawn_bundle((marker.transform, GlobalTransform::identity()));
+        entity_commands
+            .insert(marker.name.clone())
+            .insert(Enemy)
+            .with_children(|builder| {
+                builder.spawn_mesh(gltf_handle.clone(), "character", false);
+            })
+            // the same body our player gets
+            .insert_bundle(RigidBodyBundle {
+                activation: RigidBodyActivation {
+                    sleeping: false,
+                    ..Default::default()
+                },
+                body_type: RigidBodyType::Dynamic,
+                position: Isometry::from_parts(
+                    Vector::from(marker.transform.translation).into(),
+                    marker.transform.rotation.into(),
+                )
+                .into(),
+                ..Default::default()
+            })
+            .insert_bundle(ColliderBundle {
+                shape: ColliderShape::ball(0.5),
+                collider_type: ColliderType::Solid,
+                flags: (ActiveEvents::CONTACT_EVENTS | ActiveEvents::INTERSECTION_EVENTS).into(),
+                ..Default::default()
+            })
+            .insert(ColliderPositionSync::Discrete);
+        apply_extras(extras_handlers, &mut entity_commands, marker, true);
+        spawned.push(entity_commands.id());
+    }
+
+    for marker in markers.starting_with("light_") {
+        let mut entity_commands = commands.spawn_bundle(LightBundle {
+            transform: marker.transform,
+            ..Default::default()
+        });
+        entity_commands.insert(marker.name.clone());
+        apply_extras(extras_handlers, &mut entity_commands, marker, false);
+        spawned.push(entity_commands.id());
+    }
+
+    spawned
+}
+
+fn apply_extras(
+    handlers: &ExtrasHandlers,
+    entity_commands: &mut EntityCommands,
+    marker: &GltfMarker,
+    has_body: bool,
+) {
+    if let Some(extras) = &marker.extras {
+        handlers.apply(
+            entity_commands,
+            &ExtrasContext {
+                name: &marker.name,
+                extras,
+                mesh: None,
+                transform: marker.transform.into(),
+                has_body,
+                // our enemies have their body's collider
+                has_collider: has_body,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+    use serde_json::json;
+
+    use super::*;
+    use crate::mesh_loader::{MeshSpawner, Tag};
+
+    fn marker(name: &str, translation: Vec3, tag: Option<&str>) -> GltfMarker {
+        GltfMarker {
+            name: name.to_string(),
+            transform: Transform::from_translation(translation),
+            mesh: None,
+            extras: tag.map(|tag| match json!({ "tag": tag }) {
+                serde_json::Value::Object(extras) => extras,
+                _ => unreachable!(),
+            }),
+        }
+    }
+
+    #[test]
+    fn enemies_and_lights_spawn_at_our_markers() {
+        let markers = GltfMarkers {
+            nodes: vec![
+                marker("spawn_player", Vec3::new(-7., 2., -8.), None),
+                marker("spawn_enemy_1", Vec3::new(0., 1., 3.), Some("guard")),
+                marker("light_1", Vec3::new(-3., 3., 0.), Some("lamp")),
+            ],
+        };
+
+        // our enemies queue up their character mesh with our mesh spawner
+        let mut world = World::default();
+        world.insert_resource(MeshSpawner::default());
+        let mut queue = CommandQueue::default();
+        let spawned = {
+            let mut commands = Commands::new(&mut queue, &world);
+            spawn_markers(
+                &mut commands,
+                &markers,
+                &Handle::default(),
+                &ExtrasHandlers::default(),
+            )
+        };
+        queue.apply(&mut world);
+
+        // our player's marker only moves our player, it doesn't spawn anything
+        assert_eq!(spawned.len(), 2);
+
+        let enemy = spawned[0];
+        assert!(world.get::<Enemy>(enemy).is_some());
+        assert_eq!(world.get::<String>(enemy).unwrap(), "spawn_enemy_1");
+        assert_eq!(world.get::<Tag>(enemy), Some(&Tag("guard".to_string())));
+        let body = world.get::<RigidBodyPosition>(enemy).unwrap();
+        assert_eq!(
+            Vec3::from(body.position.translation.vector),
+            Vec3::new(0., 1., 3.)
+        );
+
+        let light = spawned[1];
+        assert!(world.get::<Light>(light).is_some());
+        assert!(world.get::<Enemy>(light).is_none());
+        assert_eq!(world.get::<String>(light).unwrap(), "light_1");
+        assert_eq!(world.get::<Tag>(light), Some(&Tag("lamp".to_string())));
+        assert_eq!(
+            world.get::<Transform>(light).unwrap().translation,
+            Vec3::new(-3., 3., 0.)
+        );
+    }
+}
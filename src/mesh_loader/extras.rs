@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::rapier::math::Point;
+use serde_json::{Map, Value};
+
+use crate::mesh_loader::{mesh_positions, mesh_triangles};
+
+/// The label our extras are loaded under, i.e. `models.gltf#Extras`
+pub const EXTRAS_LABEL: &str = "Extras";
+
+/// The custom properties Blender exports as gltf `extras`, for each of our meshes. Properties on
+/// the object are merged over the ones on its mesh data, the first object using a mesh wins.
+#[derive(Debug, Default, TypeUuid)]
+#[uuid = "0c3f7d52-8b8e-4a4e-b1e1-6f2d7a9e5c34"]
+pub struct GltfExtras {
+    pub meshes: HashMap<String, Map<String, Value>>,
+}
+
+/// Lets us find our entities by a `tag` from our gltf, i.e. `{"tag": "door"}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag(pub String);
+
+/// Turns a single property from our extras into components on our entity. We get the property's
+/// value along with everything else we know about our entity.
+pub type ExtrasHandler = fn(&mut EntityCommands, &Value, &ExtrasContext) -> anyhow::Result<()>;
+
+pub struct ExtrasContext<'a> {
+    /// the name of the mesh or node our extras came from, for our errors
+    pub name: &'a str,
+    /// every property on our mesh, so handlers can read the settings that go along with them
+    pub extras: &'a Map<String, Value>,
+    /// our markers don't have any meshes
+    pub mesh: Option<&'a Mesh>,
+    /// where our entity is in our world
+    pub transform: GlobalTransform,
+    /// our entity has a rigid body, so our colliders are attached to it instead of our world
+    pub has_body: bool,
+    /// our entity already has a collider, i.e. one derived from our mesh
+    pub has_collider: bool,
+}
+
+/// The handlers we run for each property in our extras, anything without a handler is ignored.
+///
+/// Our handlers run as each spawned mesh gets its extras, so any we add later only reach meshes
+/// spawned after them.
+#[derive(Clone)]
+pub struct ExtrasHandlers {
+    pub handlers: HashMap<String, ExtrasHandler>,
+}
+
+impl Default for ExtrasHandlers {
+    fn default() -> Self {
+        let mut handlers = ExtrasHandlers {
+            handlers: HashMap::new(),
+        };
+        handlers
+            .add("collider", collider_handler)
+            .add("friction", material_handler)
+            .add("restitution", material_handler)
+            .add("tag", tag_handler);
+
+        handlers
+    }
+}
+
+impl ExtrasHandlers {
+    pub fn add<S: ToString>(&mut self, property: S, handler: ExtrasHandler) -> &mut Self {
+        self.handlers.insert(property.to_string(), handler);
+
+        self
+    }
+
+    /// Run our handlers for every property in `extras`, we log our failures instead of
+    /// returning them so one bad property doesn't stop the rest
+    pub fn apply(&self, entity_commands: &mut EntityCommands, context: &ExtrasContext) {
+        for (property, value) in context.extras {
+            match self.handlers.get(property) {
+                Some(handler) => {
+                    if let Err(e) = handler(entity_commands, value, context) {
+                        log::warn!(
+                            "Couldn't apply \"{}\" from \"{}\": {:?}",
+                            property,
+                            context.name,
+                            e
+                        );
+                    }
+                }
+                None => log::trace!("Ignoring \"{}\" on \"{}\"", property, context.name),
+            }
+        }
+    }
+}
+
+/// Blender only lets us put objects in our extras, anything else is skipped
+pub(super) fn parse_extras(
+    name: &str,
+    raw: &Option<Box<serde_json::value::RawValue>>,
+) -> Option<Map<String, Value>> {
+    let raw = raw.as_ref()?;
+    match serde_json::from_str(raw.get()) {
+        Ok(Value::Object(extras)) => Some(extras),
+        Ok(_) => {
+            log::warn!("Skipping extras on \"{}\", they aren't an object", name);
+            None
+        }
+        Err(e) => {
+            log::warn!("Skipping extras on \"{}\": {:?}", name, e);
+            None
+        }
+    }
+}
+
+/// `{"collider": "convex" | "trimesh" | "cuboid"}` builds a collider from our mesh, with an
+/// optional `friction`, `restitution` and `sensor`
+fn collider_handler(
+    entity_commands: &mut EntityCommands,
+    value: &Value,
+    context: &ExtrasContext,
+) -> anyhow::Result<()> {
+    let kind = value
+        .as_str()
+        .ok_or_else(|| anyhow!("expected a string, found {}", value))?;
+    let mesh = context
+        .mesh
+        .ok_or_else(|| anyhow!("we need a mesh to build a {} collider", kind))?;
+
+    // colliders don't scale with our transform, so we bake our scale into our shape
+    let transform = context.transform;
+    let scale = Vector::from(transform.scale);
+    let positions = mesh_positions(mesh)
+        .into_iter()
+        .map(|point| Point::from(point.coords.component_mul(&scale)))
+        .collect::<Vec<_>>();
+    let shape = match kind {
+        "convex" => ColliderShape::convex_hull(&positions)
+            .ok_or_else(|| anyhow!("our mesh is too flat for a convex hull"))?,
+        "trimesh" => ColliderShape::trimesh(positions, mesh_triangles(mesh)),
+        "cuboid" => {
+            let (min, max) = positions.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), point| {
+                    let point = Vec3::new(point.x, point.y, point.z);
+                    (min.min(point), max.max(point))
+                },
+            );
+            let half_extents = (max - min) / 2.;
+            let center = (min + max) / 2.;
+            ColliderShape::compound(vec![(
+                Isometry::from(Vector::from(center)),
+                ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            )])
+        }
+        unknown => bail!("unknown collider \"{}\"", unknown),
+    };
+
+    let material = collider_material(context)?;
+    let sensor = context.extras.get("sensor").and_then(Value::as_bool) == Some(true);
+
+    // our collider sits on our body if we have one, otherwise it's fixed in our world
+    let position = if context.has_body {
+        Isometry::identity()
+    } else {
+        Isometry::from_parts(
+            Vector::from(transform.translation).into(),
+            transform.rotation.into(),
+        )
+    };
+    entity_commands.insert_bundle(ColliderBundle {
+        shape,
+        collider_type: if sensor {
+            ColliderType::Sensor
+        } else {
+            ColliderType::Solid
+        },
+        position: position.into(),
+        material,
+        ..Default::default()
+    });
+
+    Ok(())
+}
+
+/// `{"friction": 0.2}` and `{"restitution": 0.5}` without a `collider` set the material of the
+/// collider our entity already has
+fn material_handler(
+    entity_commands: &mut EntityCommands,
+    _value: &Value,
+    context: &ExtrasContext,
+) -> anyhow::Result<()> {
+    // our collider handler already built its material from these
+    if context.extras.contains_key("collider") {
+        return Ok(());
+    }
+    if !context.has_collider {
+        bail!("we don't have a collider to apply it to, add a \"collider\" as well");
+    }
+    entity_commands.insert(collider_material(context)?);
+
+    Ok(())
+}
+
+/// Our `friction` and `restitution`, defaulting to rapier's
+fn collider_material(context: &ExtrasContext) -> anyhow::Result<ColliderMaterial> {
+    let number = |property: &str, default: f32| match context.extras.get(property) {
+        Some(value) => value
+            .as_f64()
+            .map(|number| number as f32)
+            .ok_or_else(|| anyhow!("expected \"{}\" to be a number, found {}", property, value)),
+        None => Ok(default),
+    };
+    let defaults = ColliderMaterial::default();
+
+    Ok(ColliderMaterial {
+        friction: number("friction", defaults.friction)?,
+        restitution: number("restitution", defaults.restitution)?,
+        ..defaults
+    })
+}
+
+/// `{"tag": "door"}` adds a `Tag("door")`
+fn tag_handler(
+    entity_commands: &mut EntityCommands,
+    value: &Value,
+    _context: &ExtrasContext,
+) -> anyhow::Result<()> {
+    let tag = value
+        .as_str()
+        .ok_or_else(|| anyhow!("expected a string, found {}", value))?;
+    entity_commands.insert(Tag(tag.to_string()));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+    use serde_json::json;
+    use serde_json::value::RawValue;
+
+    use super::*;
+
+    /// Run whichever of our default handlers handles `property` from `extras` on a fresh entity,
+    /// with a 2 x 2 x 2 cube scaled up to twice its size as our mesh
+    fn handle(
+        property: &str,
+        extras: Value,
+        with_mesh: bool,
+        has_collider: bool,
+    ) -> (anyhow::Result<()>, World, Entity) {
+        let extras = match extras {
+            Value::Object(extras) => extras,
+            _ => panic!("our extras need to be an object"),
+        };
+        let mesh = Mesh::from(bevy::prelude::shape::Cube { size: 2. });
+        let handler = ExtrasHandlers::default().handlers[property];
+
+        let mut world = World::default();
+        let mut queue = CommandQueue::default();
+        let (result, entity) = {
+            let mut commands = Commands::new(&mut queue, &world);
+            let mut entity_commands = commands.spawn();
+            let context = ExtrasContext {
+                name: "cube",
+                extras: &extras,
+                mesh: if with_mesh { Some(&mesh) } else { None },
+                transform: GlobalTransform {
+                    translation: Vec3::new(1., 2., 3.),
+                    rotation: Quat::IDENTITY,
+                    scale: Vec3::splat(2.),
+                },
+                has_body: false,
+                has_collider,
+            };
+            let result = handler(&mut entity_commands, &extras[property], &context);
+            (result, entity_commands.id())
+        };
+        queue.apply(&mut world);
+
+        (result, world, entity)
+    }
+
+    fn raw(json: &str) -> Option<Box<RawValue>> {
+        Some(RawValue::from_string(json.to_string()).unwrap())
+    }
+
+    #[test]
+    fn only_objects_are_extras() {
+        let extras = parse_extras("cube", &raw(r#"{"tag": "door", "friction": 0.2}"#)).unwrap();
+        assert_eq!(extras["tag"], json!("door"));
+        assert_eq!(extras["friction"], json!(0.2));
+
+        assert_eq!(parse_extras("cube", &raw("[1, 2]")), None);
+        assert_eq!(parse_extras("cube", &raw("\"door\"")), None);
+        assert_eq!(parse_extras("cube", &None), None);
+    }
+
+    #[test]
+    fn cuboid_colliders_fit_our_scaled_mesh() {
+        let extras = json!({
+            "collider": "cuboid",
+            "friction": 0.2,
+            "restitution": 0.5,
+            "sensor": true,
+        });
+        let (result, world, entity) = handle("collider", extras, true, false);
+        result.unwrap();
+
+        let shape = world.get::<ColliderShape>(entity).unwrap();
+        let (offset, cuboid) = &shape.as_compound().unwrap().shapes()[0];
+        assert_eq!(offset.translation.vector, Vector::zeros());
+        assert_eq!(
+            cuboid.as_cuboid().unwrap().half_extents,
+            Vector::new(2., 2., 2.)
+        );
+
+        // without a body our collider stands where our entity does
+        let position = world.get::<ColliderPosition>(entity).unwrap();
+        assert_eq!(position.0.translation.vector, Vector::new(1., 2., 3.));
+
+        let material = world.get::<ColliderMaterial>(entity).unwrap();
+        assert_eq!((material.friction, material.restitution), (0.2, 0.5));
+        assert_eq!(
+            world.get::<ColliderType>(entity),
+            Some(&ColliderType::Sensor)
+        );
+    }
+
+    #[test]
+    fn mesh_colliders_keep_our_scaled_vertices() {
+        let (result, world, entity) =
+            handle("collider", json!({"collider": "trimesh"}), true, false);
+        result.unwrap();
+        let shape = world.get::<ColliderShape>(entity).unwrap();
+        let trimesh = shape.as_trimesh().unwrap();
+        assert_eq!(trimesh.indices().len(), 12);
+        assert!(trimesh
+            .vertices()
+            .iter()
+            .all(|point| point.coords.amax() == 2.));
+
+        let (result, world, entity) =
+            handle("collider", json!({"collider": "convex"}), true, false);
+        result.unwrap();
+        let shape = world.get::<ColliderShape>(entity).unwrap();
+        assert!(shape.as_convex_polyhedron().is_some());
+        assert_eq!(shape.compute_local_aabb().maxs, Point::new(2., 2., 2.));
+
+        // and we stick to rapier's material and a solid collider if we aren't told otherwise
+        let material = world.get::<ColliderMaterial>(entity).unwrap();
+        let defaults = ColliderMaterial::default();
+        assert_eq!(
+            (material.friction, material.restitution),
+            (defaults.friction, defaults.restitution)
+        );
+        assert_eq!(
+            world.get::<ColliderType>(entity),
+            Some(&ColliderType::Solid)
+        );
+    }
+
+    #[test]
+    fn bad_colliders_are_errors() {
+        let bad = [
+            (json!({"collider": "sphere"}), true),
+            (json!({"collider": 3}), true),
+            (json!({"collider": "cuboid", "friction": "slippery"}), true),
+            // our markers don't have a mesh to build one from
+            (json!({"collider": "cuboid"}), false),
+        ];
+        for (extras, with_mesh) in bad.iter() {
+            let (result, world, entity) = handle("collider", extras.clone(), *with_mesh, false);
+            assert!(result.is_err(), "{} should fail", extras);
+            assert!(world.get::<ColliderShape>(entity).is_none());
+        }
+    }
+
+    #[test]
+    fn materials_change_the_collider_we_already_have() {
+        let (result, world, entity) = handle("friction", json!({"friction": 0.1}), true, true);
+        result.unwrap();
+        let material = world.get::<ColliderMaterial>(entity).unwrap();
+        assert_eq!(material.friction, 0.1);
+        assert_eq!(
+            material.restitution,
+            ColliderMaterial::default().restitution
+        );
+
+        let (result, _, _) = handle("restitution", json!({"restitution": 0.9}), true, false);
+        assert!(result.is_err());
+
+        // our collider handler takes care of them when we have one of those too
+        let extras = json!({"collider": "cuboid", "restitution": 0.9});
+        let (result, world, entity) = handle("restitution", extras, true, false);
+        result.unwrap();
+        assert!(world.get::<ColliderMaterial>(entity).is_none());
+    }
+
+    #[test]
+    fn tags_tag_our_entities() {
+        let (result, world, entity) = handle("tag", json!({"tag": "door"}), false, false);
+        result.unwrap();
+        assert_eq!(world.get::<Tag>(entity), Some(&Tag("door".to_string())));
+
+        let (result, world, entity) = handle("tag", json!({"tag": 5}), false, false);
+        assert!(result.is_err());
+        assert!(world.get::<Tag>(entity).is_none());
+    }
+}
@@ -3,6 +3,8 @@ use bevy::prelude::*;
 
 pub trait EnhancedGltf {
     fn get_mesh<'a>(&self, name: &str, gltf_meshes: &'a Assets<GltfMesh>) -> &'a GltfMesh;
+
+    fn find_mesh<'a>(&self, name: &str, gltf_meshes: &'a Assets<GltfMesh>) -> Option<&'a GltfMesh>;
 }
 
 impl EnhancedGltf for Gltf {
@@ -19,4 +21,10 @@ impl EnhancedGltf for Gltf {
             )
         }
     }
+
+    fn find_mesh<'a>(&self, name: &str, gltf_meshes: &'a Assets<GltfMesh>) -> Option<&'a GltfMesh> {
+        self.named_meshes
+            .get(name)
+            .and_then(|mesh_handle| gltf_meshes.get(mesh_handle))
+    }
 }
@@ -0,0 +1,206 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::gltf::GltfLoader;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde_json::{Map, Value};
+
+use crate::mesh_loader::extras::{parse_extras, GltfExtras, EXTRAS_LABEL};
+
+/// The label our markers are loaded under, i.e. `models.gltf#Markers`
+pub const MARKERS_LABEL: &str = "Markers";
+
+/// Every named node in a gltf along with where it ends up in our world, so empties placed in
+/// Blender can mark where things should spawn
+#[derive(Debug, Default, TypeUuid)]
+#[uuid = "6a0f3c8e-5d0b-4c55-9a43-3f5d2f0b9c71"]
+pub struct GltfMarkers {
+    pub nodes: Vec<GltfMarker>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfMarker {
+    pub name: String,
+    /// where our node sits in our scene, including the transforms of all of its parents
+    pub transform: Transform,
+    /// the name of the mesh on our node, empties don't have one
+    pub mesh: Option<String>,
+    /// the custom properties on our node
+    pub extras: Option<Map<String, Value>>,
+}
+
+impl GltfMarkers {
+    pub fn get(&self, name: &str) -> Option<&GltfMarker> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    /// Every node whose name starts with `prefix`, i.e. `spawn_enemy_`
+    pub fn starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a GltfMarker> {
+        self.nodes
+            .iter()
+            .filter(move |node| node.name.starts_with(prefix))
+    }
+
+    /// Our nodes without any meshes
+    pub fn empties(&self) -> impl Iterator<Item = &GltfMarker> {
+        self.nodes.iter().filter(|node| node.mesh.is_none())
+    }
+}
+
+/// Loads our gltfs exactly like bevy does, and then adds our markers and extras alongside them
+#[derive(Default)]
+pub struct MarkedGltfLoader {
+    gltf: GltfLoader,
+}
+
+impl AssetLoader for MarkedGltfLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.gltf.load(bytes, load_context).await?;
+
+            let gltf = gltf::Gltf::from_slice(bytes)?;
+            load_context.set_labeled_asset(MARKERS_LABEL, LoadedAsset::new(find_markers(&gltf)));
+            load_context.set_labeled_asset(EXTRAS_LABEL, LoadedAsset::new(find_extras(&gltf)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.gltf.extensions()
+    }
+}
+
+fn find_markers(gltf: &gltf::Gltf) -> GltfMarkers {
+    let mut markers = GltfMarkers::default();
+    for scene in gltf.scenes() {
+        for node in scene.nodes() {
+            add_markers(&mut markers, &node, Mat4::IDENTITY);
+        }
+    }
+
+    markers
+}
+
+fn add_markers(markers: &mut GltfMarkers, node: &gltf::Node, parent: Mat4) {
+    let matrix = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(name) = node.name() {
+        markers.nodes.push(GltfMarker {
+            name: name.to_string(),
+            transform: Transform::from_matrix(matrix),
+            mesh: node
+                .mesh()
+                .and_then(|mesh| mesh.name().map(|name| name.to_string())),
+            extras: parse_extras(name, node.extras()),
+        });
+    }
+
+    for child in node.children() {
+        add_markers(markers, &child, matrix);
+    }
+}
+
+fn find_extras(gltf: &gltf::Gltf) -> GltfExtras {
+    let mut extras = GltfExtras::default();
+    for mesh in gltf.meshes() {
+        if let Some(name) = mesh.name() {
+            if let Some(mesh_extras) = parse_extras(name, mesh.extras()) {
+                extras.meshes.insert(name.to_string(), mesh_extras);
+            }
+        }
+    }
+
+    // our object's properties win over its mesh's, but only the first object using a mesh gets to
+    // set them
+    let mut claimed = vec![];
+    for node in gltf.nodes() {
+        let mesh_name = match node
+            .mesh()
+            .and_then(|mesh| mesh.name().map(|name| name.to_string()))
+        {
+            Some(mesh_name) => mesh_name,
+            None => continue,
+        };
+        if claimed.contains(&mesh_name) {
+            continue;
+        }
+        let node_extras = match parse_extras(node.name().unwrap_or(&mesh_name), node.extras()) {
+            Some(node_extras) => node_extras,
+            None => continue,
+        };
+
+        extras
+            .meshes
+            .entry(mesh_name.clone())
+            .or_default()
+            .extend(node_extras);
+        claimed.push(mesh_name);
+    }
+
+    extras
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn models() -> GltfMarkers {
+        find_markers(&gltf::Gltf::from_slice(include_bytes!("../../assets/models.gltf")).unwrap())
+    }
+
+    #[test]
+    fn our_markers_sit_where_our_scene_puts_them() {
+        let markers = models();
+
+        let player = markers.get("spawn_player").unwrap();
+        assert_eq!(player.transform.translation, Vec3::new(-7., 2., -8.));
+        assert_eq!(player.mesh, None);
+        assert_eq!(player.extras, None);
+
+        // our lamp hangs off of a parent that's raised up and turned a quarter to the left
+        let light = markers.get("light_1").unwrap();
+        assert!(
+            (light.transform.translation - Vec3::new(-3., 3., 0.)).length() < 1e-5,
+            "{}",
+            light.transform.translation
+        );
+        assert!(
+            (light.transform.rotation * Vec3::X - Vec3::new(0., 0., -1.)).length() < 1e-5,
+            "{}",
+            light.transform.rotation
+        );
+        assert_eq!(light.extras.as_ref().unwrap()["tag"], json!("lamp"));
+    }
+
+    #[test]
+    fn our_markers_can_be_found_by_their_names() {
+        let markers = models();
+
+        let enemies = markers.starting_with("spawn_enemy_").collect::<Vec<_>>();
+        assert_eq!(enemies.len(), 1);
+        assert_eq!(enemies[0].name, "spawn_enemy_1");
+        assert_eq!(enemies[0].transform.translation, Vec3::new(0., 1., 3.));
+        assert_eq!(enemies[0].extras.as_ref().unwrap()["tag"], json!("guard"));
+
+        assert_eq!(markers.get("wall").unwrap().mesh.as_deref(), Some("wall"));
+        let empties = markers
+            .empties()
+            .map(|node| node.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            empties,
+            [
+                "Point",
+                "spawn_player",
+                "spawn_enemy_1",
+                "lights",
+                "light_1"
+            ]
+        );
+    }
+}
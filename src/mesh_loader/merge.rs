@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::pipeline::PrimitiveTopology;
+
+/// Our per vertex colors. Bevy's own pbr shader doesn't read these, so meshes with our occlusion
+/// are drawn with our `OCCLUDED_PIPELINE_HANDLE` which multiplies our base color by them.
+pub const ATTRIBUTE_COLOR: &str = "Vertex_Color";
+
+/// Bake a copy of `mesh` at every one of our `transforms` into a single mesh, so a whole chunk of
+/// floor tiles can be drawn as one entity.
+///
+/// `occlusion` optionally shades each copy with how exposed its north west, north east, south east
+/// and south west corners are (1 is fully lit), blended across our mesh into its vertex colors.
+pub fn merge_instances(
+    mesh: &Mesh,
+    transforms: &[Transform],
+    occlusion: Option<&[[f32; 4]]>,
+) -> Mesh {
+    let positions = float3_attribute(mesh, Mesh::ATTRIBUTE_POSITION);
+    let normals = float3_attribute(mesh, Mesh::ATTRIBUTE_NORMAL);
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float2(values)) => values.clone(),
+        Some(_) => panic!("Right now we only handle the Float2 uv type"),
+        None => vec![[0., 0.]; positions.len()],
+    };
+    let indices = match mesh.indices() {
+        Some(Indices::U32(raw_indices)) => raw_indices.clone(),
+        Some(Indices::U16(raw_indices)) => raw_indices.iter().map(|i| *i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let vertex_count = positions.len() * transforms.len();
+    let mut merged_positions: Vec<[f32; 3]> = Vec::with_capacity(vertex_count);
+    let mut merged_normals: Vec<[f32; 3]> = Vec::with_capacity(vertex_count);
+    let mut merged_uvs: Vec<[f32; 2]> = Vec::with_capacity(vertex_count);
+    let mut merged_indices = Vec::with_capacity(indices.len() * transforms.len());
+    let mut merged_colors: Vec<[f32; 4]> = Vec::with_capacity(vertex_count);
+
+    // where each of our vertices sits across our mesh from its north west to south east corner
+    let (min, max) = positions.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), p| (min.min(Vec3::from(*p)), max.max(Vec3::from(*p))),
+    );
+    let extent = (max - min).max(Vec3::splat(f32::EPSILON));
+    let across = positions
+        .iter()
+        .map(|p| (Vec3::from(*p) - min) / extent)
+        .collect::<Vec<_>>();
+
+    for (index, transform) in transforms.iter().enumerate() {
+        let matrix = transform.compute_matrix();
+        // keep our normals perpendicular to our surfaces however we've been transformed
+        let normal_matrix = matrix.inverse().transpose();
+        let base = merged_positions.len() as u32;
+
+        for p in positions.iter() {
+            merged_positions.push(matrix.transform_point3(Vec3::from(*p)).into());
+        }
+        for n in normals.iter() {
+            merged_normals.push(
+                normal_matrix
+                    .transform_vector3(Vec3::from(*n))
+                    .normalize()
+                    .into(),
+            );
+        }
+        merged_uvs.extend(uvs.iter().cloned());
+        merged_indices.extend(indices.iter().map(|i| base + i));
+
+        if let Some(occlusion) = occlusion {
+            let [north_west, north_east, south_east, south_west] = occlusion[index];
+            merged_colors.extend(across.iter().map(|a| {
+                let north = north_west + (north_east - north_west) * a.x;
+                let south = south_west + (south_east - south_west) * a.x;
+                let light = north + (south - north) * a.z;
+                [light, light, light, 1.]
+            }));
+        }
+    }
+
+    let mut merged = Mesh::new(PrimitiveTopology::TriangleList);
+    merged.set_attribute(Mesh::ATTRIBUTE_POSITION, merged_positions);
+    merged.set_attribute(Mesh::ATTRIBUTE_NORMAL, merged_normals);
+    merged.set_attribute(Mesh::ATTRIBUTE_UV_0, merged_uvs);
+    if occlusion.is_some() {
+        merged.set_attribute(ATTRIBUTE_COLOR, merged_colors);
+    }
+    merged.set_indices(Some(Indices::U32(merged_indices)));
+
+    merged
+}
+
+fn float3_attribute(mesh: &Mesh, name: &'static str) -> Vec<[f32; 3]> {
+    match mesh.attribute(name) {
+        Some(VertexAttributeValues::Float3(values)) => values.clone(),
+        _ => panic!("Right now we only handle the Float3 vertex type"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn triangle(positions: [[f32; 3]; 3], normal: [f32; 3]) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions.to_vec());
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, vec![normal; 3]);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.], [1., 0.], [0., 1.]]);
+        mesh.set_indices(Some(Indices::U16(vec![0, 2, 1])));
+        mesh
+    }
+
+    fn assert_close(actual: &[[f32; 3]], expected: &[Vec3]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (Vec3::from(*actual) - *expected).length() < 1e-5,
+                "{:?} isn't {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn instances_follow_one_another_in_our_merged_mesh() {
+        let mesh = triangle([[0., 0., 0.], [1., 0., 0.], [0., 0., 1.]], [0., 1., 0.]);
+        let merged = merge_instances(
+            &mesh,
+            &[
+                Transform::from_xyz(2., 0., 0.),
+                // stood up on its side, facing south
+                Transform {
+                    translation: Vec3::new(0., 1., 0.),
+                    rotation: Quat::from_rotation_x(FRAC_PI_2),
+                    scale: Vec3::new(1., 1., 2.),
+                },
+            ],
+            None,
+        );
+
+        assert_close(
+            &float3_attribute(&merged, Mesh::ATTRIBUTE_POSITION),
+            &[
+                Vec3::new(2., 0., 0.),
+                Vec3::new(3., 0., 0.),
+                Vec3::new(2., 0., 1.),
+                Vec3::new(0., 1., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., -1., 0.),
+            ],
+        );
+        assert_close(
+            &float3_attribute(&merged, Mesh::ATTRIBUTE_NORMAL),
+            &[Vec3::Y, Vec3::Y, Vec3::Y, Vec3::Z, Vec3::Z, Vec3::Z],
+        );
+        match merged.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float2(uvs)) => {
+                assert_eq!(uvs[..3], uvs[3..]);
+            }
+            _ => panic!("our merged mesh lost its uvs"),
+        }
+        match merged.indices() {
+            Some(Indices::U32(indices)) => assert_eq!(indices, &[0, 2, 1, 3, 5, 4]),
+            _ => panic!("our merged mesh should have u32 indices"),
+        }
+        assert!(merged.attribute(ATTRIBUTE_COLOR).is_none());
+    }
+
+    #[test]
+    fn stretched_instances_keep_their_normals_perpendicular() {
+        // a slope facing north east, which leans back when we stretch it east
+        let slope = Vec3::new(1., 1., 0.).normalize();
+        let mesh = triangle([[1., 0., 0.], [0., 1., 0.], [0., 1., 1.]], slope.into());
+        let merged = merge_instances(&mesh, &[Transform::from_scale(Vec3::new(2., 1., 1.))], None);
+
+        let positions = float3_attribute(&merged, Mesh::ATTRIBUTE_POSITION);
+        let normals = float3_attribute(&merged, Mesh::ATTRIBUTE_NORMAL);
+        assert_close(&normals, &[Vec3::new(1., 2., 0.).normalize(); 3]);
+        let [a, b, c] = [positions[0], positions[1], positions[2]];
+        let normal = Vec3::from(normals[0]);
+        assert!(normal.dot(Vec3::from(b) - Vec3::from(a)).abs() < 1e-5);
+        assert!(normal.dot(Vec3::from(c) - Vec3::from(a)).abs() < 1e-5);
+    }
+}
@@ -1,6 +1,15 @@
+mod extras;
 mod gltf;
+mod markers;
+mod merge;
+mod occluded;
 
 use crate::mesh_loader::gltf::EnhancedGltf;
+use crate::mesh_loader::markers::MarkedGltfLoader;
+use crate::mesh_loader::merge::merge_instances;
+use crate::mesh_loader::occluded::{add_occluded_pipeline, occluded_render_pipelines};
+use bevy::asset::AssetPath;
+use bevy::ecs::entity::Entities;
 use bevy::ecs::system::Command;
 use bevy::gltf::{Gltf, GltfMesh, GltfPrimitive};
 use bevy::prelude::*;
@@ -8,63 +17,151 @@ use bevy::render::mesh::{Indices, VertexAttributeValues};
 use bevy::render::pipeline::PrimitiveTopology;
 use bevy_rapier3d::prelude::*;
 use bevy_rapier3d::rapier::math::Point;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+pub use extras::{ExtrasContext, ExtrasHandler, ExtrasHandlers, GltfExtras, Tag, EXTRAS_LABEL};
+pub use markers::{GltfMarker, GltfMarkers, MARKERS_LABEL};
+pub use merge::ATTRIBUTE_COLOR;
+pub use occluded::OCCLUDED_PIPELINE_HANDLE;
+
+/// Spawns meshes out of our gltfs along with their markers and extras.
+///
+/// Insert your own `ExtrasHandlers` before adding us to replace our default handlers.
 pub struct MeshLoaderPlugin;
 
 impl Plugin for MeshLoaderPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.init_resource::<MeshSpawner>().add_system_to_stage(
-            CoreStage::PreUpdate,
-            mesh_spawner_system.exclusive_system().at_end(),
-        );
+        app.init_resource::<MeshSpawner>()
+            .init_resource::<ExtrasHandlers>()
+            .add_asset::<GltfMarkers>()
+            .add_asset::<GltfExtras>()
+            // we replace bevy's own gltf loader, so we need to come after bevy's plugins
+            .init_asset_loader::<MarkedGltfLoader>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                mesh_spawner_system.exclusive_system().at_end(),
+            );
+        add_occluded_pipeline(app);
     }
 }
 
 #[derive(Default)]
-struct MeshSpawner {
+pub(crate) struct MeshSpawner {
     meshes_to_spawn: HashMap<Handle<Gltf>, Vec<SpawnGltfMeshInfo>>,
     physics_meshes: HashMap<Handle<Mesh>, ColliderShape>,
+    /// the extras that were loaded along with each of our gltfs
+    extras: HashMap<Handle<Gltf>, Handle<GltfExtras>>,
+    /// the meshes we've spawned that still need our extras applied
+    extras_to_apply: Vec<SpawnedGltfMesh>,
+    /// the meshes we've already warned about ignoring the extras of
+    ignored_extras: HashSet<String>,
+}
+
+/// We apply our extras a frame after spawning our mesh, once our entity has been placed in our
+/// world, and keep trying for this many frames before giving up on an entity that never is
+const EXTRAS_ATTEMPTS: u32 = 30;
+
+/// A mesh we spawned from our gltf, which we still need to apply our extras to
+struct SpawnedGltfMesh {
+    gltf: Handle<Gltf>,
+    mesh_name: String,
+    mesh: Handle<Mesh>,
+    entity: Entity,
+    extras_on: ExtrasTarget,
+    attempts: u32,
+}
+
+/// What the extras of our gltf mesh end up on
+enum ExtrasTarget {
+    /// our entity, which draws our gltf mesh as is
+    Entity,
+    /// a child of our entity at each of the copies of our mesh we merged together
+    Instances(Vec<Transform>),
+    /// nothing, we drew a mesh of our own with our gltf's material
+    Ignored,
 }
 
 impl MeshSpawner {
-    fn created_gltf(
+    fn spawn_meshes(
         &mut self,
         handle: &Handle<Gltf>,
         gltfs: &Assets<Gltf>,
         gltf_meshes: &Assets<GltfMesh>,
-        meshes: &Assets<Mesh>,
+        meshes: &mut Assets<Mesh>,
+        entities: &Entities,
         commands: &mut Commands,
     ) {
         for SpawnGltfMeshInfo {
             mesh_name,
+            missing,
             derive_physics_shape,
+            source,
             entity,
         } in self
             .meshes_to_spawn
             .remove(&handle)
             .unwrap_or_default()
             .into_iter()
+            // our entity may have been despawned while we were waiting on our gltf
+            .filter(|info| entities.contains(info.entity))
         {
             let gltf = gltfs.get(handle).unwrap();
-            let gltf_mesh = gltf.get_mesh(&mesh_name, &gltf_meshes);
+            let (drawn_name, gltf_mesh) = match (gltf.find_mesh(&mesh_name, &gltf_meshes), &missing)
+            {
+                (Some(gltf_mesh), _) => (&mesh_name, gltf_mesh),
+                (None, MissingMesh::Fallback(fallback)) => {
+                    (fallback, gltf.get_mesh(fallback, &gltf_meshes))
+                }
+                (None, MissingMesh::Panic) => (&mesh_name, gltf.get_mesh(&mesh_name, &gltf_meshes)),
+            };
 
             // todo can we have multiple meshes in 1 gltf mesh?
             let gltf_primitive = gltf_mesh.primitives.get(0).unwrap();
 
+            self.extras_to_apply.push(SpawnedGltfMesh {
+                gltf: handle.clone(),
+                mesh_name: drawn_name.clone(),
+                mesh: gltf_primitive.mesh.clone(),
+                entity,
+                extras_on: match &source {
+                    MeshSource::Gltf => ExtrasTarget::Entity,
+                    MeshSource::Merged(transforms, _) => {
+                        ExtrasTarget::Instances(transforms.clone())
+                    }
+                    MeshSource::Custom(_) => ExtrasTarget::Ignored,
+                },
+                attempts: 0,
+            });
+
+            let occluded = matches!(source, MeshSource::Merged(_, Some(_)));
+            let mesh = match source {
+                MeshSource::Gltf => gltf_primitive.mesh.clone(),
+                // bake all of our instances into a mesh of their own
+                MeshSource::Merged(transforms, occlusion) => {
+                    let mesh = meshes.get(&gltf_primitive.mesh).unwrap();
+                    let merged = merge_instances(mesh, &transforms, occlusion.as_deref());
+                    meshes.add(merged)
+                }
+                MeshSource::Custom(mesh) => meshes.add(mesh),
+            };
+
             // create a Pbr bundle and pull out the pieces we want
-            let pbr = if let Some(material) = &gltf_primitive.material {
+            let mut pbr = if let Some(material) = &gltf_primitive.material {
                 PbrBundle {
-                    mesh: gltf_primitive.mesh.clone(),
+                    mesh,
                     material: material.clone(),
                     ..Default::default()
                 }
             } else {
                 PbrBundle {
-                    mesh: gltf_primitive.mesh.clone(),
+                    mesh,
                     ..Default::default()
                 }
             };
+            // bevy's pbr shader ignores our vertex colors, so our occlusion needs its own pipeline
+            if occluded {
+                pbr.render_pipelines = occluded_render_pipelines();
+            }
 
             let mut entity_commands = commands.entity(entity);
 
@@ -83,6 +180,25 @@ impl MeshSpawner {
         }
     }
 
+    /// The extras that were loaded alongside our gltf, `None` if it wasn't loaded from a file
+    fn extras_handle(
+        &mut self,
+        handle: &Handle<Gltf>,
+        asset_server: &AssetServer,
+    ) -> Option<Handle<GltfExtras>> {
+        if let Some(extras_handle) = self.extras.get(handle) {
+            return Some(extras_handle.clone());
+        }
+
+        // our extras are a labeled asset of the same file, so they finish loading with our gltf
+        let path = asset_server.get_handle_path(handle)?;
+        let extras_handle: Handle<GltfExtras> =
+            asset_server.get_handle(AssetPath::new_ref(path.path(), Some(EXTRAS_LABEL)));
+        self.extras.insert(handle.clone(), extras_handle.clone());
+
+        Some(extras_handle)
+    }
+
     fn derive_physics_shape(
         &mut self,
         gltf_primitive: &GltfPrimitive,
@@ -94,61 +210,192 @@ impl MeshSpawner {
                 let mesh = meshes.get(&gltf_primitive.mesh).unwrap();
                 log::trace!("Deriving Physics Shape");
 
-                match mesh.primitive_topology() {
-                    PrimitiveTopology::TriangleList => {
-                        let vertex_position_attributes =
-                            mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
-                        let positions = match vertex_position_attributes {
-                            VertexAttributeValues::Float3(values) => values
-                                .iter()
-                                .map(|p| Into::<Point<_>>::into(*p))
-                                .collect::<Vec<_>>(),
-                            _ => panic!("Right now we only handle the Float3 vertex type"),
-                        };
-                        let indices = match mesh.indices().unwrap() {
-                            Indices::U32(raw_indices) => raw_indices
-                                .chunks(3)
-                                .map(|c| [c[0], c[1], c[2]])
-                                .collect::<Vec<_>>(),
-                            Indices::U16(raw_indices) => raw_indices
-                                .chunks(3)
-                                .map(|c| [c[0] as u32, c[1] as u32, c[2] as u32])
-                                .collect::<Vec<_>>(),
-                        };
-
-                        ColliderShape::trimesh(positions, indices)
-                    }
-                    unknown => {
-                        panic!(
-                            "We can't generate a ColliderShape from this topology: {:?}",
-                            unknown
-                        )
-                    }
-                }
+                ColliderShape::trimesh(mesh_positions(mesh), mesh_triangles(mesh))
             })
             .clone()
     }
 }
 
+/// The positions of our mesh's vertices
+fn mesh_positions(mesh: &Mesh) -> Vec<Point<Real>> {
+    match mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap() {
+        VertexAttributeValues::Float3(values) => values
+            .iter()
+            .map(|p| Into::<Point<_>>::into(*p))
+            .collect::<Vec<_>>(),
+        _ => panic!("Right now we only handle the Float3 vertex type"),
+    }
+}
+
+/// The triangles of our mesh, as indices into `mesh_positions`
+fn mesh_triangles(mesh: &Mesh) -> Vec<[u32; 3]> {
+    match mesh.primitive_topology() {
+        PrimitiveTopology::TriangleList => match mesh.indices().unwrap() {
+            Indices::U32(raw_indices) => raw_indices
+                .chunks(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<_>>(),
+            Indices::U16(raw_indices) => raw_indices
+                .chunks(3)
+                .map(|c| [c[0] as u32, c[1] as u32, c[2] as u32])
+                .collect::<Vec<_>>(),
+        },
+        unknown => {
+            panic!(
+                "We can't generate a ColliderShape from this topology: {:?}",
+                unknown
+            )
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn mesh_spawner_system(
     mut spawner: ResMut<MeshSpawner>,
-    mut loaded_gltf: EventReader<AssetEvent<Gltf>>,
+    asset_server: Res<AssetServer>,
     gltfs: Res<Assets<Gltf>>,
     gltf_meshes: Res<Assets<GltfMesh>>,
-    meshes: Res<Assets<Mesh>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    gltf_extras: Res<Assets<GltfExtras>>,
+    extras_handlers: Res<ExtrasHandlers>,
+    entities: &Entities,
+    placement_query: Query<(
+        Option<&GlobalTransform>,
+        Option<&RigidBodyPosition>,
+        Option<&ColliderShape>,
+    )>,
     mut commands: Commands,
 ) {
-    for event in loaded_gltf.iter() {
-        // ignore our other events, we already correctly update our models while running
-        if let AssetEvent::Created { handle } = event {
-            spawner.created_gltf(&handle, &gltfs, &gltf_meshes, &meshes, &mut commands);
+    // apply the extras of the meshes we spawned before this frame, so our commands have placed
+    // them in our world and added any colliders we derived for them
+    let spawner = &mut *spawner;
+    for mut spawned in std::mem::take(&mut spawner.extras_to_apply) {
+        // our entity may have been despawned before we got to it
+        if !entities.contains(spawned.entity) {
+            continue;
+        }
+        let extras = match spawner.extras_handle(&spawned.gltf, &asset_server) {
+            Some(extras_handle) => match gltf_extras.get(&extras_handle) {
+                Some(extras) => extras,
+                None => {
+                    spawner.extras_to_apply.push(spawned);
+                    continue;
+                }
+            },
+            // our gltf wasn't loaded from a file, so it can't have any extras
+            None => continue,
+        };
+        let mesh_extras = match extras.meshes.get(&spawned.mesh_name) {
+            Some(mesh_extras) => mesh_extras,
+            None => continue,
+        };
+
+        // don't guess where our entity is, wait until it's been placed
+        let (transform, body, collider) = match placement_query.get(spawned.entity) {
+            Ok((Some(transform), body, collider)) => (*transform, body, collider),
+            _ => {
+                spawned.attempts += 1;
+                if spawned.attempts < EXTRAS_ATTEMPTS {
+                    spawner.extras_to_apply.push(spawned);
+                } else {
+                    log::warn!(
+                        "Skipping the extras on \"{}\", its entity was never placed in our world",
+                        spawned.mesh_name
+                    );
+                }
+                continue;
+            }
+        };
+
+        let mesh = meshes.get(&spawned.mesh);
+        match &spawned.extras_on {
+            ExtrasTarget::Entity => extras_handlers.apply(
+                &mut commands.entity(spawned.entity),
+                &ExtrasContext {
+                    name: &spawned.mesh_name,
+                    extras: mesh_extras,
+                    mesh,
+                    transform,
+                    has_body: body.is_some(),
+                    has_collider: collider.is_some(),
+                },
+            ),
+            // each of our copies is an object of its own, so it gets an entity of its own
+            ExtrasTarget::Instances(instances) => {
+                commands.entity(spawned.entity).with_children(|parent| {
+                    for instance in instances.iter() {
+                        let instance_transform = transform.mul_transform(*instance);
+                        let mut instance_commands =
+                            parent.spawn_bundle((*instance, instance_transform));
+                        instance_commands.insert(spawned.mesh_name.clone());
+                        extras_handlers.apply(
+                            &mut instance_commands,
+                            &ExtrasContext {
+                                name: &spawned.mesh_name,
+                                extras: mesh_extras,
+                                mesh,
+                                transform: instance_transform,
+                                has_body: false,
+                                has_collider: false,
+                            },
+                        );
+                    }
+                });
+            }
+            ExtrasTarget::Ignored => {
+                if spawner.ignored_extras.insert(spawned.mesh_name.clone()) {
+                    log::warn!(
+                        "Ignoring the extras on \"{}\", we only borrowed its material for a mesh of our own",
+                        spawned.mesh_name
+                    );
+                }
+            }
         }
     }
+
+    // spawn anything that's waiting on a gltf that has finished loading, this covers both meshes
+    // queued before our gltf loaded and meshes queued long after it did
+    let loaded = spawner
+        .meshes_to_spawn
+        .keys()
+        .filter(|handle| gltfs.get(*handle).is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    for handle in loaded {
+        spawner.spawn_meshes(
+            &handle,
+            &gltfs,
+            &gltf_meshes,
+            &mut meshes,
+            entities,
+            &mut commands,
+        );
+    }
+}
+
+/// What to do when our gltf doesn't have the mesh we asked for
+pub enum MissingMesh {
+    Panic,
+    /// use this mesh instead
+    Fallback(String),
+}
+
+/// Where the mesh we draw comes from, it's always drawn with the material of our gltf mesh
+enum MeshSource {
+    /// our gltf mesh as is
+    Gltf,
+    /// merge a copy of our gltf mesh at each of these transforms, optionally shaded by the
+    /// occlusion of each copy's corners
+    Merged(Vec<Transform>, Option<Vec<[f32; 4]>>),
+    /// a mesh we built ourselves
+    Custom(Mesh),
 }
 
 struct SpawnGltfMeshInfo {
     mesh_name: String,
+    missing: MissingMesh,
     derive_physics_shape: bool,
+    source: MeshSource,
     entity: Entity,
 }
 
@@ -177,6 +424,25 @@ pub trait SpawnMeshAsChildCommands {
         mesh_name: S,
         derive_physics_mesh: bool,
     ) -> &mut Self;
+
+    /// Like `spawn_mesh` but draws a copy of our mesh at every one of `transforms` as a single
+    /// merged mesh, with `occlusion` baked into the vertex colors of each copy's corners
+    fn spawn_merged_mesh<S: ToString>(
+        &mut self,
+        gltf_handle: Handle<Gltf>,
+        mesh_name: S,
+        missing: MissingMesh,
+        transforms: Vec<Transform>,
+        occlusion: Option<Vec<[f32; 4]>>,
+    ) -> &mut Self;
+
+    /// Draw a `mesh` we built ourselves with the material of one of our gltf's meshes
+    fn spawn_custom_mesh<S: ToString>(
+        &mut self,
+        gltf_handle: Handle<Gltf>,
+        material_mesh_name: S,
+        mesh: Mesh,
+    ) -> &mut Self;
 }
 
 impl<'a, 'b> SpawnMeshAsChildCommands for ChildBuilder<'a, 'b> {
@@ -190,7 +456,51 @@ impl<'a, 'b> SpawnMeshAsChildCommands for ChildBuilder<'a, 'b> {
             gltf_handle,
             info: SpawnGltfMeshInfo {
                 mesh_name: mesh_name.to_string(),
+                missing: MissingMesh::Panic,
                 derive_physics_shape: derive_physics_mesh,
+                source: MeshSource::Gltf,
+                entity: self.parent_entity(),
+            },
+        });
+
+        self
+    }
+
+    fn spawn_merged_mesh<S: ToString>(
+        &mut self,
+        gltf_handle: Handle<Gltf>,
+        mesh_name: S,
+        missing: MissingMesh,
+        transforms: Vec<Transform>,
+        occlusion: Option<Vec<[f32; 4]>>,
+    ) -> &mut Self {
+        self.add_command(SpawnGltfMesh {
+            gltf_handle,
+            info: SpawnGltfMeshInfo {
+                mesh_name: mesh_name.to_string(),
+                missing,
+                derive_physics_shape: false,
+                source: MeshSource::Merged(transforms, occlusion),
+                entity: self.parent_entity(),
+            },
+        });
+
+        self
+    }
+
+    fn spawn_custom_mesh<S: ToString>(
+        &mut self,
+        gltf_handle: Handle<Gltf>,
+        material_mesh_name: S,
+        mesh: Mesh,
+    ) -> &mut Self {
+        self.add_command(SpawnGltfMesh {
+            gltf_handle,
+            info: SpawnGltfMeshInfo {
+                mesh_name: material_mesh_name.to_string(),
+                missing: MissingMesh::Panic,
+                derive_physics_shape: false,
+                source: MeshSource::Custom(mesh),
                 entity: self.parent_entity(),
             },
         });
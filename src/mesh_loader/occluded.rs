@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::pipeline::{
+    BlendFactor, BlendOperation, BlendState, ColorTargetState, ColorWrite, CompareFunction,
+    DepthBiasState, DepthStencilState, PipelineDescriptor, StencilFaceState, StencilState,
+};
+use bevy::render::shader::{ShaderStage, ShaderStages};
+use bevy::render::texture::TextureFormat;
+
+/// Bevy's pbr pipeline with its base color multiplied by our `Vertex_Color`, for the meshes we
+/// baked occlusion into
+pub const OCCLUDED_PIPELINE_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(PipelineDescriptor::TYPE_UUID, 7283625103318424811);
+
+pub(super) fn add_occluded_pipeline(app: &mut AppBuilder) {
+    let world = app.world_mut();
+    let pipeline =
+        build_occluded_pipeline(&mut world.get_resource_mut::<Assets<Shader>>().unwrap());
+    world
+        .get_resource_mut::<Assets<PipelineDescriptor>>()
+        .unwrap()
+        .set_untracked(OCCLUDED_PIPELINE_HANDLE, pipeline);
+}
+
+/// Our render pipelines for a mesh with occlusion in its vertex colors
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) fn occluded_render_pipelines() -> RenderPipelines {
+    RenderPipelines::from_pipelines(vec![bevy::render::pipeline::RenderPipeline::new(
+        OCCLUDED_PIPELINE_HANDLE.typed(),
+    )])
+}
+
+/// webgl2 needs its own GLSL ES version of every shader, so for now our web builds draw our
+/// occluded meshes with bevy's own pipeline and skip our occlusion
+#[cfg(target_arch = "wasm32")]
+pub(super) fn occluded_render_pipelines() -> RenderPipelines {
+    RenderPipelines::default()
+}
+
+/// The same pipeline as `bevy_pbr::build_pbr_pipeline`, just with our own shaders
+fn build_occluded_pipeline(shaders: &mut Assets<Shader>) -> PipelineDescriptor {
+    PipelineDescriptor {
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState {
+                front: StencilFaceState::IGNORE,
+                back: StencilFaceState::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+            bias: DepthBiasState {
+                constant: 0,
+                slope_scale: 0.0,
+                clamp: 0.0,
+            },
+            clamp_depth: false,
+        }),
+        color_target_states: vec![ColorTargetState {
+            format: TextureFormat::default(),
+            color_blend: BlendState {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
+            alpha_blend: BlendState {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+            write_mask: ColorWrite::ALL,
+        }],
+        ..PipelineDescriptor::new(ShaderStages {
+            vertex: shaders.add(Shader::from_glsl(
+                ShaderStage::Vertex,
+                include_str!("occluded.vert"),
+            )),
+            fragment: Some(shaders.add(Shader::from_glsl(
+                ShaderStage::Fragment,
+                include_str!("occluded.frag"),
+            ))),
+        })
+    }
+}
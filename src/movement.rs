@@ -1,28 +1,153 @@
+use std::collections::VecDeque;
+
 use crate::aim_system::MouseLight;
+use crate::level::{
+    find_path, waypoints, Chunk, ChunkManager, CurrentLevel, LevelGrid, LevelSource, PathCache,
+    PathOptions,
+};
 use crate::player::PlayerControlled;
 use crate::view_system::{run_first_person, run_third_person};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
 const MOVE_SENSITIVITY: f32 = 0.2;
+const THIRD_PERSON_SPEED: f32 = 4.;
+/// how long we need to hold our button before a click turns into steering
+const HOLD_TO_STEER_SECONDS: f64 = 0.25;
+/// how close we need to get to a waypoint before moving on to the next one
+const WAYPOINT_DISTANCE: f32 = 0.2;
+/// how long we can go without getting any closer to our waypoint before we give up
+const BLOCKED_SECONDS: f32 = 1.;
 
 pub struct MovePlugin;
 
 impl Plugin for MovePlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system_set(
-            SystemSet::new()
-                .with_run_criteria(run_first_person.system())
-                .with_system(first_person_move_system.system()),
-        )
-        .add_system_set(
-            SystemSet::new()
-                .with_run_criteria(run_third_person.system())
-                .with_system(third_person_move_system.system()),
-        );
+        app.init_resource::<MoveSettings>()
+            .add_event::<MoveArrived>()
+            .add_event::<MoveBlocked>()
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(run_first_person.system())
+                    .with_system(first_person_move_system.system()),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(run_third_person.system())
+                    .with_system(third_person_move_system.system().label("click_to_move"))
+                    .with_system(follow_path_system.system().after("click_to_move")),
+            );
+    }
+}
+
+pub struct MoveSettings {
+    /// holding our button steers straight towards our cursor instead of following a path
+    pub drag_to_steer: bool,
+    pub path_options: PathOptions,
+}
+
+impl Default for MoveSettings {
+    fn default() -> Self {
+        MoveSettings {
+            drag_to_steer: true,
+            path_options: PathOptions::default(),
+        }
+    }
+}
+
+/// The waypoints we're walking along after a click
+#[derive(Default)]
+pub struct PathFollower {
+    waypoints: VecDeque<Vec3>,
+    /// the closest we've been to our next waypoint, so we can tell when we're stuck
+    closest: f32,
+    stuck_time: f32,
+}
+
+impl PathFollower {
+    fn follow(&mut self, waypoints: Vec<Vec3>) {
+        self.waypoints = waypoints.into();
+        self.closest = f32::MAX;
+        self.stuck_time = 0.;
+    }
+
+    fn stop(&mut self) {
+        self.waypoints.clear();
+    }
+
+    pub fn destination(&self) -> Option<Vec3> {
+        self.waypoints.back().cloned()
+    }
+
+    /// Move on past the waypoints we've reached standing at `position`, working out how to walk
+    /// towards the next one or whether we've arrived or gotten stuck on the way. `None` if we
+    /// aren't going anywhere.
+    fn advance(&mut self, position: Vec3, delta_seconds: f32) -> Option<FollowStep> {
+        let destination = self.destination()?;
+
+        // move on to our next waypoint once we're close enough to this one
+        let mut distance_vector = Vec3::ZERO;
+        while let Some(waypoint) = self.waypoints.front() {
+            distance_vector = *waypoint - position;
+            distance_vector.y = 0.;
+
+            if distance_vector.length() > WAYPOINT_DISTANCE {
+                break;
+            }
+            self.waypoints.pop_front();
+            self.closest = f32::MAX;
+            self.stuck_time = 0.;
+        }
+
+        if self.waypoints.is_empty() {
+            return Some(FollowStep::Arrived(destination));
+        }
+
+        // give up if we've been walking into something
+        let distance = distance_vector.length();
+        if distance < self.closest - 0.01 {
+            self.closest = distance;
+            self.stuck_time = 0.;
+        } else {
+            self.stuck_time += delta_seconds;
+        }
+        if self.stuck_time > BLOCKED_SECONDS {
+            self.stop();
+            return Some(FollowStep::Blocked(destination));
+        }
+
+        // check to see if we're really close and should just step the rest of the way
+        let mut distance_translation = distance_vector.normalize() * THIRD_PERSON_SPEED;
+        if distance < distance_translation.length() {
+            distance_translation = distance_vector;
+        }
+
+        Some(FollowStep::Walk(distance_translation))
     }
 }
 
+/// What our `PathFollower` does next
+#[derive(Debug, PartialEq)]
+enum FollowStep {
+    /// walk along our ground with this velocity
+    Walk(Vec3),
+    Arrived(Vec3),
+    Blocked(Vec3),
+}
+
+/// Sent when we make it to where we clicked
+pub struct MoveArrived {
+    pub entity: Entity,
+    pub destination: Vec3,
+}
+
+/// Sent when there's no way to where we clicked, including while our level is still loading, or
+/// we stop making progress on the way there
+pub struct MoveBlocked {
+    pub entity: Entity,
+    pub destination: Vec3,
+}
+
 fn first_person_move_system(
     keyboard_input: Res<Input<KeyCode>>,
     //modified to use rigid body position instead of transform because update direction
@@ -74,22 +199,89 @@ fn rotate_vec3_by_quat(quat: Quat, vec: Vec3) -> Vec3 {
         + 2.0 * quat.w * quat_vec.cross(vec)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn third_person_move_system(
+    time: Res<Time>,
     mouse_input: Res<Input<MouseButton>>,
+    settings: Res<MoveSettings>,
+    grid: Res<LevelGrid>,
+    level_source: Res<LevelSource>,
+    current_level: Option<Res<CurrentLevel>>,
+    chunks: Res<ChunkManager>,
+    mut path_cache: ResMut<PathCache>,
+    mut pressed_at: Local<f64>,
+    mut blocked_events: EventWriter<MoveBlocked>,
     mouse_query: Query<&GlobalTransform, With<MouseLight>>,
-    mut player_query: Query<(&mut RigidBodyVelocity, &GlobalTransform), With<PlayerControlled>>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut RigidBodyVelocity,
+            &GlobalTransform,
+            &mut PathFollower,
+        ),
+        With<PlayerControlled>,
+    >,
 ) {
-    if mouse_input.pressed(MouseButton::Right) {
-        let mouse_location = mouse_query.iter().next().unwrap().translation;
+    let mouse_location = match mouse_query.iter().next() {
+        Some(transform) => transform.translation,
+        None => return,
+    };
+
+    // a click sets where we're going
+    if mouse_input.just_pressed(MouseButton::Right) {
+        *pressed_at = time.seconds_since_startup();
+
+        for (entity, _, player_transform, mut follower) in player_query.iter_mut() {
+            let (start, goal) = (player_transform.translation, mouse_location);
+            let waypoints = match &*level_source {
+                LevelSource::Caves | LevelSource::Dungeon | LevelSource::Asset(_) => {
+                    current_level.as_ref().and_then(|level| {
+                        let (chunk, offset) = level.chunk()?;
+                        plan_path(&grid, chunk, offset, start, goal, |chunk, from, to| {
+                            let generation = level.generation();
+                            path_cache.find_path(chunk, generation, from, to, settings.path_options)
+                        })
+                    })
+                }
+                LevelSource::Streamed => {
+                    // we path across every chunk we've spawned between us and our click
+                    let (region, offset) = chunks.loaded_region(
+                        chunks.chunk_coord(&grid, start),
+                        chunks.chunk_coord(&grid, goal),
+                    );
+                    plan_path(&grid, &region, offset, start, goal, |chunk, from, to| {
+                        find_path(chunk, from, to, settings.path_options)
+                    })
+                }
+            };
+
+            match waypoints {
+                Some(waypoints) => follower.follow(waypoints),
+                None => {
+                    follower.stop();
+                    blocked_events.send(MoveBlocked {
+                        entity,
+                        destination: mouse_location,
+                    });
+                }
+            }
+        }
+    }
+    // while holding our button we can just steer straight at our cursor
+    else if settings.drag_to_steer
+        && mouse_input.pressed(MouseButton::Right)
+        && time.seconds_since_startup() - *pressed_at > HOLD_TO_STEER_SECONDS
+    {
+        for (_, mut player_velocity, player_transform, mut follower) in player_query.iter_mut() {
+            follower.stop();
 
-        for (mut player_velocity, player_transform) in player_query.iter_mut() {
             let mut distance_vector = mouse_location - player_transform.translation;
             distance_vector.y = 0.; // clear out vertical movement
 
             // check to see if we're already at our location
             if distance_vector.length() > 0. {
                 // get our move velocity
-                let mut distance_translation = distance_vector.normalize() * 4.;
+                let mut distance_translation = distance_vector.normalize() * THIRD_PERSON_SPEED;
 
                 // check to see if we're really close and should just step the rest of the way
                 if distance_vector.length() < distance_translation.length() {
@@ -104,3 +296,197 @@ fn third_person_move_system(
         }
     }
 }
+
+fn follow_path_system(
+    time: Res<Time>,
+    mut arrived_events: EventWriter<MoveArrived>,
+    mut blocked_events: EventWriter<MoveBlocked>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut RigidBodyVelocity,
+            &GlobalTransform,
+            &mut PathFollower,
+        ),
+        With<PlayerControlled>,
+    >,
+) {
+    for (entity, mut player_velocity, player_transform, mut follower) in player_query.iter_mut() {
+        let step = match follower.advance(player_transform.translation, time.delta_seconds()) {
+            Some(step) => step,
+            None => continue,
+        };
+
+        match step {
+            FollowStep::Walk(velocity) => {
+                player_velocity.linvel =
+                    Vector::new(velocity.x, player_velocity.linvel.y, velocity.z);
+            }
+            FollowStep::Arrived(destination) => {
+                player_velocity.linvel = Vector::new(0., player_velocity.linvel.y, 0.);
+                arrived_events.send(MoveArrived {
+                    entity,
+                    destination,
+                });
+            }
+            FollowStep::Blocked(destination) => {
+                player_velocity.linvel = Vector::new(0., player_velocity.linvel.y, 0.);
+                blocked_events.send(MoveBlocked {
+                    entity,
+                    destination,
+                });
+            }
+        }
+    }
+}
+
+/// The waypoints from `start` to `goal` across `chunk`, whose `(0, 0)` cell sits on the `offset`
+/// tile of our grid, skipping the cell we're already in and ending exactly on `goal`. `None` if
+/// either end is off our chunk or `find` can't find a way between them.
+fn plan_path<F>(
+    grid: &LevelGrid,
+    chunk: &Chunk,
+    offset: (i32, i32),
+    start: Vec3,
+    goal: Vec3,
+    find: F,
+) -> Option<Vec<Vec3>>
+where
+    F: FnOnce(&Chunk, (usize, usize), (usize, usize)) -> Option<Vec<(usize, usize)>>,
+{
+    let cell = |position| {
+        let (x, z) = grid.world_to_tile(position);
+        let (x, z) = (x - offset.0, z - offset.1);
+        if x >= 0 && z >= 0 {
+            Some((x as usize, z as usize))
+        } else {
+            None
+        }
+    };
+
+    let path = find(chunk, cell(start)?, cell(goal)?)?;
+    let mut points = waypoints(grid, offset, &path[1..]);
+    points.pop();
+    points.push(goal);
+
+    Some(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::Tile;
+
+    use super::*;
+
+    fn following(waypoints: &[Vec3]) -> PathFollower {
+        let mut follower = PathFollower::default();
+        follower.follow(waypoints.to_vec());
+        follower
+    }
+
+    #[test]
+    fn we_walk_on_to_our_next_waypoint_once_we_reach_one() {
+        let mut follower = following(&[Vec3::new(5., 0., 0.), Vec3::new(5., 0., 5.)]);
+
+        // a long way off we head straight for our first waypoint at full speed, ignoring height
+        match follower.advance(Vec3::new(0., 3., 0.), 0.1) {
+            Some(FollowStep::Walk(velocity)) => {
+                assert_eq!(velocity, Vec3::X * THIRD_PERSON_SPEED)
+            }
+            step => panic!("we should be walking, not {:?}", step),
+        }
+
+        // close enough to it we turn towards the next
+        match follower.advance(Vec3::new(4.9, 0., 0.), 0.1) {
+            Some(FollowStep::Walk(velocity)) => assert!(velocity.z > 0.99 * velocity.length()),
+            step => panic!("we should be walking, not {:?}", step),
+        }
+        assert_eq!(follower.waypoints.len(), 1);
+
+        // and step the last little bit rather than overshooting
+        assert_eq!(
+            follower.advance(Vec3::new(5., 0., 3.), 0.1),
+            Some(FollowStep::Walk(Vec3::new(0., 0., 2.)))
+        );
+    }
+
+    #[test]
+    fn we_arrive_at_our_last_waypoint() {
+        let destination = Vec3::new(2., 0., 1.);
+        let mut follower = following(&[Vec3::new(1., 0., 1.), destination]);
+        follower.advance(Vec3::new(1., 0., 1.), 0.1);
+
+        assert_eq!(
+            follower.advance(Vec3::new(2.1, 0., 1.), 0.1),
+            Some(FollowStep::Arrived(destination))
+        );
+        assert_eq!(follower.destination(), None);
+        assert_eq!(follower.advance(Vec3::new(2.1, 0., 1.), 0.1), None);
+    }
+
+    #[test]
+    fn we_give_up_when_we_stop_getting_closer() {
+        let destination = Vec3::new(4., 0., 0.);
+        let mut follower = following(&[destination]);
+
+        // making progress keeps us going however long it takes
+        for step in 0..20 {
+            let position = Vec3::new(step as f32 * 0.1, 0., 0.);
+            assert!(matches!(
+                follower.advance(position, 0.5),
+                Some(FollowStep::Walk(_))
+            ));
+        }
+
+        // but walking into a wall doesn't
+        let stuck = Vec3::new(2., 0., 0.);
+        let mut steps = vec![];
+        for _ in 0..5 {
+            steps.push(follower.advance(stuck, 0.3));
+        }
+        assert!(steps[..4]
+            .iter()
+            .all(|step| matches!(step, Some(FollowStep::Walk(_)))));
+        assert_eq!(steps[4], Some(FollowStep::Blocked(destination)));
+        assert_eq!(follower.destination(), None);
+    }
+
+    #[test]
+    fn planned_paths_end_where_we_clicked() {
+        // .#.
+        // ...
+        // with the wall in our way we have to go around
+        let mut chunk = Chunk::new(3, 2, Tile::Floor);
+        chunk.set(1, 0, Tile::Wall);
+        let grid = LevelGrid::default();
+        let offset = (-1, 2);
+        let find = |chunk: &Chunk, from, to| find_path(chunk, from, to, PathOptions::default());
+
+        let goal = Vec3::new(1.2, 0., 2.1);
+        let points = plan_path(&grid, &chunk, offset, Vec3::new(-1., 0., 2.), goal, find).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                Vec3::new(-1., 0., 3.),
+                Vec3::new(0., 0., 3.),
+                Vec3::new(1., 0., 3.),
+                goal
+            ]
+        );
+
+        // clicking a wall or off our chunk gets us nowhere
+        let start = Vec3::new(-1., 0., 2.);
+        assert_eq!(
+            plan_path(&grid, &chunk, offset, start, Vec3::new(0., 0., 2.), find),
+            None
+        );
+        assert_eq!(
+            plan_path(&grid, &chunk, offset, start, Vec3::new(-3., 0., 2.), find),
+            None
+        );
+        assert_eq!(
+            plan_path(&grid, &chunk, offset, start, Vec3::new(5., 0., 2.), find),
+            None
+        );
+    }
+}
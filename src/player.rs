@@ -1,2 +1,3 @@
 pub struct Player;
 pub struct PlayerControlled;
+pub struct Enemy;
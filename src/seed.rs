@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::args::find_arg;
+
+pub struct SeedPlugin;
+
+impl Plugin for SeedPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // keep any seed we were given before our plugin was added
+        let seed = *app
+            .world_mut()
+            .get_resource_or_insert_with(WorldSeed::from_args);
+        log::info!(
+            "World seed {}, pass --seed {} to see this world again",
+            seed.0,
+            seed.0
+        );
+
+        app.insert_resource(WorldRng::new(seed));
+    }
+}
+
+/// The one seed our whole world grows from, the same seed always gives us the same world. Set it
+/// with `--seed <seed>`, or `run_with_seed` on the web.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    /// A fresh seed every run
+    fn default() -> Self {
+        WorldSeed(rand::random())
+    }
+}
+
+impl WorldSeed {
+    /// The seed from our `--seed <seed>` argument, or a fresh one if we weren't given one
+    pub fn from_args() -> Self {
+        let seed = find_arg(std::env::args(), "--seed").map(|value| {
+            value.and_then(|value| {
+                value
+                    .parse()
+                    .map_err(|e| anyhow!("\"{}\" isn't a seed: {}", value, e))
+            })
+        });
+        match seed {
+            Some(Ok(seed)) => WorldSeed(seed),
+            Some(Err(e)) => {
+                log::warn!("Ignoring our --seed: {:?}", e);
+                WorldSeed::default()
+            }
+            None => WorldSeed::default(),
+        }
+    }
+
+    /// Derive the seed for the named stream, e.g. `"loot"` or `"ai"`. Our hash is spelled out here
+    /// rather than borrowed from std so it's the same on every platform and every release.
+    pub fn derive(self, name: &str) -> u64 {
+        // fnv-1a over our name, mixed into our seed with splitmix64
+        let name = name.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        });
+
+        let mut hash = self.0 ^ name;
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        hash ^ (hash >> 31)
+    }
+
+    /// A fresh rng for the named stream, starting from the beginning every time
+    pub fn rng(self, name: &str) -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(self.derive(name))
+    }
+
+    /// Split off a seed of our own for the named stream, so it can split again in turn
+    pub fn split(self, name: &str) -> WorldSeed {
+        WorldSeed(self.derive(name))
+    }
+}
+
+/// Our running rng streams, one per subsystem so rolling loot never changes what our AI decides.
+/// Each stream picks up where it left off, for something that should come out the same no matter
+/// when it's asked for (like a chunk) derive an rng from our `WorldSeed` instead.
+pub struct WorldRng {
+    seed: WorldSeed,
+    streams: HashMap<String, ChaCha8Rng>,
+}
+
+impl WorldRng {
+    pub fn new(seed: WorldSeed) -> WorldRng {
+        WorldRng {
+            seed,
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+
+    /// The rng for the named stream
+    pub fn stream(&mut self, name: &str) -> &mut ChaCha8Rng {
+        let seed = self.seed;
+        self.streams
+            .entry(name.to_string())
+            .or_insert_with(|| seed.rng(name))
+    }
+
+    /// Our own streams for a subsystem with streams of its own, i.e. each of our enemies
+    pub fn split(&self, name: &str) -> WorldRng {
+        WorldRng::new(self.seed.split(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn streams_are_independent_and_stable() {
+        let seed = WorldSeed(1234);
+        assert_eq!(seed.derive("chunks"), WorldSeed(1234).derive("chunks"));
+        assert_ne!(seed.derive("chunks"), seed.derive("loot"));
+        assert_ne!(seed.derive("chunks"), WorldSeed(1235).derive("chunks"));
+    }
+
+    /// The next few numbers from a stream
+    fn draw(rng: &mut ChaCha8Rng) -> Vec<u64> {
+        (0..8).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn one_seed_gives_the_same_streams() {
+        let mut first = WorldRng::new(WorldSeed(99));
+        let mut second = WorldRng::new(WorldSeed(99));
+
+        // however our streams are interleaved
+        let loot = draw(first.stream("loot"));
+        let ai = draw(first.stream("ai"));
+        assert_eq!(draw(second.stream("ai")), ai);
+        assert_eq!(draw(second.stream("loot")), loot);
+
+        // and they pick up where they left off
+        assert_eq!(draw(first.stream("loot")), draw(second.stream("loot")));
+        assert_eq!(
+            draw(first.split("enemy").stream("ai")),
+            draw(second.split("enemy").stream("ai"))
+        );
+    }
+
+    #[test]
+    fn differently_named_streams_are_independent() {
+        let mut world = WorldRng::new(WorldSeed(99));
+        let loot = draw(world.stream("loot"));
+        let ai = draw(world.stream("ai"));
+        assert_ne!(loot, ai);
+
+        // rolling one stream doesn't move any other
+        let mut fresh = WorldRng::new(WorldSeed(99));
+        draw(fresh.stream("ai"));
+        draw(fresh.stream("ai"));
+        assert_eq!(draw(fresh.stream("loot")), loot);
+
+        // and our splits are streams of their own
+        let mut split = world.split("loot");
+        assert_ne!(draw(split.stream("loot")), loot);
+        assert_ne!(draw(WorldRng::new(WorldSeed(100)).stream("loot")), loot);
+    }
+}
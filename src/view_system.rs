@@ -30,6 +30,8 @@ pub struct ViewPlugin;
 pub enum ViewKind {
     First,
     Third,
+    /// our third person view with the mouse editing our level
+    Editor,
 }
 
 impl Plugin for ViewPlugin {
@@ -38,7 +40,7 @@ impl Plugin for ViewPlugin {
             .add_system(switch_camera_view_system.system())
             .add_system_set(
                 SystemSet::new()
-                    .with_run_criteria(run_third_person.system())
+                    .with_run_criteria(run_third_person_camera.system())
                     .with_system(third_person_system.system()),
             )
             .add_system_set(
@@ -75,9 +77,16 @@ fn switch_camera_view_system(
     mut windows: ResMut<Windows>,
     mut view_kind: ResMut<ViewKind>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Insert) || keyboard_input.just_pressed(KeyCode::Grave) {
-        let window = windows.get_primary_mut().unwrap();
+    let switch_view =
+        keyboard_input.just_pressed(KeyCode::Insert) || keyboard_input.just_pressed(KeyCode::Grave);
+    let switch_editor = keyboard_input.just_pressed(KeyCode::F2);
+    // only borrow our window mutably when we're changing it, otherwise it's marked as changed
+    if !switch_view && !switch_editor {
+        return;
+    }
+    let window = windows.get_primary_mut().unwrap();
 
+    if switch_view {
         *view_kind = match *view_kind {
             ViewKind::First => {
                 // give up our mouse
@@ -85,7 +94,7 @@ fn switch_camera_view_system(
                 window.set_cursor_visibility(true);
                 ViewKind::Third
             }
-            ViewKind::Third => {
+            ViewKind::Third | ViewKind::Editor => {
                 // grab our mouse
                 window.set_cursor_lock_mode(true);
                 window.set_cursor_visibility(false);
@@ -93,6 +102,17 @@ fn switch_camera_view_system(
             }
         }
     }
+
+    if switch_editor {
+        // our editor needs our mouse
+        window.set_cursor_lock_mode(false);
+        window.set_cursor_visibility(true);
+
+        *view_kind = match *view_kind {
+            ViewKind::Editor => ViewKind::Third,
+            ViewKind::First | ViewKind::Third => ViewKind::Editor,
+        }
+    }
 }
 
 impl ViewKind {
@@ -113,6 +133,18 @@ pub fn run_third_person(view_kind: Res<ViewKind>) -> ShouldRun {
     ViewKind::Third.should_run(&*view_kind)
 }
 
+pub fn run_editor(view_kind: Res<ViewKind>) -> ShouldRun {
+    ViewKind::Editor.should_run(&*view_kind)
+}
+
+/// Our editor looks at the world the same way our third person view does
+pub fn run_third_person_camera(view_kind: Res<ViewKind>) -> ShouldRun {
+    match *view_kind {
+        ViewKind::Third | ViewKind::Editor => ShouldRun::Yes,
+        ViewKind::First => ShouldRun::No,
+    }
+}
+
 #[allow(clippy::type_complexity)]
 fn first_person_system(
     mut ev_mouse: EventReader<MouseMotion>,